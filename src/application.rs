@@ -12,15 +12,38 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 //! General types applicable to any Application
-use std::path::Path;
+use std::any::Any;
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::borrow::Cow;
+#[cfg(any(feature = "metrics", feature = "health", feature = "metrics-statsd"))]
+use std::net::SocketAddr;
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
+use chan;
+use chan::Sender;
 use chan::Receiver;
 use chan_signal::Signal;
 
-use logging::LogOptions;
+use clock::{Clock, SystemClock};
+#[cfg(feature = "control")]
+use control::ControlSocket;
+use info::ServiceInfo;
+use limits::Resource;
+use logging::{self, LogOptions};
+#[cfg(feature = "metrics-statsd")]
+use statsd;
+use sysexits;
+use systemd;
 
 /// Indicates whether the run loop should halt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Stopping {
     /// The run loop should halt
     Yes,
@@ -29,56 +52,1389 @@ pub enum Stopping {
     No
 }
 
+impl Stopping {
+    /// Whether this is `Stopping::Yes`
+    pub fn is_yes(self) -> bool {
+        self == Stopping::Yes
+    }
+
+    /// Whether this is `Stopping::No`
+    pub fn is_no(self) -> bool {
+        self == Stopping::No
+    }
+
+    /// `Stopping::Yes` if either `self` or `other` is
+    pub fn combine(self, other: Stopping) -> Stopping {
+        Stopping::from(self.is_yes() || other.is_yes())
+    }
+}
+
+/// `true` means `Stopping::Yes`
+impl From<bool> for Stopping {
+    fn from(stop: bool) -> Stopping {
+        if stop {
+            Stopping::Yes
+        } else {
+            Stopping::No
+        }
+    }
+}
+
+/// What the run loop should do after an iteration; see `Application::run_step`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Loop {
+    /// Halt, as for `Stopping::Yes`
+    Stop,
+
+    /// Carry on, as for `Stopping::No`
+    Continue,
+
+    /// Carry on after sleeping this long, in place of `tick_interval`
+    ///
+    /// A signal arriving meanwhile ends the sleep early. Requests to the
+    /// metrics, health, and control servers are answered during the sleep.
+    Sleep(Duration),
+}
+
+impl From<Stopping> for Loop {
+    fn from(stopping: Stopping) -> Loop {
+        match stopping {
+            Stopping::Yes => Loop::Stop,
+            Stopping::No => Loop::Continue,
+        }
+    }
+}
+
+/// When `run` calls `Context::poll_signals` on the application's behalf
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PollMode {
+    /// Only when the application calls it from `run_once`
+    Never,
+
+    /// Before each call to `run_once`
+    Before,
+
+    /// After each call to `run_once`
+    After,
+
+    /// Both before and after each call to `run_once`
+    Both,
+}
+
+/// What `Context::poll_signals` does with a signal; see
+/// `Application::signal_action`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalAction {
+    /// Stop the main loop, then drain and shut down
+    Shutdown,
+
+    /// Load the config again and pass it to `Application::reload_config`
+    Reload,
+
+    /// Set `Context::drain_requested` and keep running
+    Drain,
+
+    /// Pass the signal to `Application::received_signal`
+    Custom,
+}
+
+/// Why the main loop stopped; passed to `Application::shutdown_with`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownReason {
+    /// `run_once` returned `Stopping::Yes` on its own
+    Completed,
+
+    /// A signal handler returned `Stopping::Yes` for this signal
+    Signal(Signal),
+
+    /// `Context::request_stop` or a `StopHandle` was used
+    StopRequested,
+
+    /// `Application::max_idle` passed without work or signals
+    Idle,
+}
+
+/// Result of a health or readiness check
+#[cfg(feature = "health")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Health {
+    /// The check passed
+    Healthy,
+
+    /// The check failed for the given reason
+    Unhealthy(String),
+}
+
+/// Process exit code carried by an application error
+///
+/// Used by `run_with_code` to let supervisors distinguish failure modes.
+pub trait ExitCode {
+    /// Code the process should exit with
+    ///
+    /// Defaults to 1, matching `die!`.
+    fn exit_code(&self) -> i32 {
+        1
+    }
+}
+
+/// Error returned when CLI options can't be loaded
+#[derive(Debug)]
+pub struct OptionsError(Box<dyn error::Error + Send + Sync>);
+
+impl OptionsError {
+    /// Wrap an error or message describing why the options are invalid
+    pub fn new<E>(err: E) -> OptionsError
+        where E: Into<Box<dyn error::Error + Send + Sync>>
+    {
+        OptionsError(err.into())
+    }
+}
+
+impl fmt::Display for OptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl error::Error for OptionsError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&*self.0)
+    }
+}
+
+/// Error returned when the config can't be loaded
+#[derive(Debug)]
+pub struct ConfigError(Box<dyn error::Error + Send + Sync>);
+
+impl ConfigError {
+    /// Wrap an error or message describing why the config couldn't be loaded
+    pub fn new<E>(err: E) -> ConfigError
+        where E: Into<Box<dyn error::Error + Send + Sync>>
+    {
+        ConfigError(err.into())
+    }
+
+    /// The wrapped error
+    ///
+    /// This can be downcast to inspect the cause, e.g. to tell apart the
+    /// variants of a `FileConfigError`.
+    pub fn get_ref(&self) -> &(dyn error::Error + Send + Sync + 'static) {
+        &*self.0
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&*self.0)
+    }
+}
+
 /// Trait required for loading Config from file
-pub trait Config {
-    /// Parse options and return it
+pub trait Config: Sized {
+    /// Parse config and return it
+    ///
+    /// A failure at startup exits the process with the error message. A
+    /// failure while reloading on SIGHUP is logged and the reload is skipped.
+    fn load<O: Options>(_: &O) -> Result<Self, ConfigError>;
+
+    /// Override loaded values with environment variables starting with `prefix`
+    ///
+    /// Called after `load` when `Options::env_prefix` is set, so values from
+    /// the environment take precedence over those from the file. Variables
+    /// which aren't set must leave the corresponding value untouched. The
+    /// default implementation does nothing.
+    fn apply_env(&mut self, _prefix: &str) -> Result<(), ConfigError> {
+        Ok(())
+    }
+
+    /// Check that the loaded values make sense together
+    ///
+    /// Called after `load` and `apply_env`, including on reloads, so an
+    /// error here is treated like one from `load`. It's also what
+    /// `Options::check_only` reports on. The default accepts anything.
+    fn validate(&self) -> Result<(), ConfigError> {
+        Ok(())
+    }
+}
+
+/// A `Config` for services without a config file
+///
+/// Loading always succeeds without reading anything, so an application with
+/// `type Config = NoConfig` needn't implement `Options::config_path`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NoConfig;
+
+impl Config for NoConfig {
+    fn load<O: Options>(_: &O) -> Result<NoConfig, ConfigError> {
+        Ok(NoConfig)
+    }
+}
+
+/// Trait required for loading CLI options
+///
+/// `run` keeps a copy of the options for reloading the config, since
+/// `Application::new` consumes them, hence the `Clone` bound.
+pub trait Options : LogOptions + Clone + Send + Sync + 'static {
+    /// Parse options and return them
+    fn load() -> Result<Self, OptionsError> where Self: Sized;
+
+    /// Parse options, printing the error and exiting on failure
+    ///
+    /// This is what `run` uses, and matches the behavior of implementations
+    /// which used `die!` before `load` was fallible.
+    fn load_or_die() -> Self where Self: Sized {
+        Self::load().unwrap_or_else(|err| die!("Invalid options: {}", err))
+    }
+
+    /// Get the path to a config file
+    ///
+    /// This method is needed since Config::load is expected to be generic. The
+    /// default is an empty path, meaning there is no config file, which is
+    /// only suitable along with `NoConfig`. Nothing is watched for changes in
+    /// that case.
+    fn config_path<'a>(&'a self) -> Cow<'a, Path> {
+        Cow::Borrowed(Path::new(""))
+    }
+
+    /// Config files to merge, lowest precedence first
+    ///
+    /// Used by `Layered`, such as for a system-wide file followed by a
+    /// per-instance one. Only `config_path` is watched for changes. Defaults
+    /// to just `config_path`, or nothing when it's empty.
+    fn config_paths(&self) -> Vec<PathBuf> {
+        let path = self.config_path();
+        if path.as_os_str().is_empty() {
+            Vec::new()
+        } else {
+            vec![path.into_owned()]
+        }
+    }
+
+    /// Prefix of environment variables which override config values
+    ///
+    /// See `Config::apply_env`. Defaults to `None`, disabling overrides.
+    fn env_prefix(&self) -> Option<String> {
+        None
+    }
+
+    /// Only validate the config, then exit
+    ///
+    /// When true, as for a `--check-config` flag, `run` loads and validates
+    /// the config, prints the result, and exits with status 0 or
+    /// `sysexits::CONFIG`. This happens before any other setup, so nothing
+    /// is daemonized, no PID file is written, no privileges are dropped, and
+    /// `Application::new` isn't called. Defaults to false.
+    fn check_only(&self) -> bool {
+        false
+    }
+
+    /// Watch the config file and reload it when it changes
+    ///
+    /// Changes are passed to `Application::config_changed`. Defaults to
+    /// `false`.
+    #[cfg(feature = "watch")]
+    fn watch_config(&self) -> bool {
+        false
+    }
+
+    /// How long the config file must go unmodified before it is reloaded
+    ///
+    /// Editors often save in several steps, such as writing a temporary file
+    /// and renaming it over the original, so changes are debounced. Defaults
+    /// to 500ms.
+    #[cfg(feature = "watch")]
+    fn watch_debounce(&self) -> Duration {
+        Duration::from_millis(500)
+    }
+
+    /// Path to write the process id to once the application has been created
+    ///
+    /// The file is created after the chroot but before privileges are dropped;
+    /// see `chroot_dir`. It's removed when `run` returns, which needs its
+    /// directory to be writable by the `run_as_user`. If it already names a
+    /// running process, startup is aborted; a stale file is replaced.
+    fn pid_file<'a>(&'a self) -> Option<Cow<'a, Path>> {
+        None
+    }
+
+    /// Detach from the terminal and run in the background
+    ///
+    /// When true, `run` double-forks before installing signal handlers,
+    /// initializing logging, or creating the application. Stdio is redirected
+    /// to `/dev/null`. Ignored when `foreground` is true.
+    fn daemonize(&self) -> bool {
+        false
+    }
+
+    /// Stay in the foreground, as for a `--foreground` flag
+    ///
+    /// This beats `daemonize`, so a service configured to daemonize can still
+    /// be run in a terminal for debugging. Logging then goes to stderr rather
+    /// than stdout, with `ColorChoice::Auto` coloring it when stderr is a
+    /// terminal; `LogOptions::writer` still takes precedence. Defaults to
+    /// false.
+    fn foreground(&self) -> bool {
+        false
+    }
+
+    /// Whether `run` should block and handle signals at all
+    ///
+    /// Returning false leaves every signal with its default disposition, for
+    /// supervisors which manage the lifecycle themselves. `Context` then never
+    /// receives a signal, so `poll_signals` does nothing, and TERM or INT
+    /// terminate the process immediately without `drain` or `shutdown`.
+    /// Defaults to true.
+    fn manage_signals(&self) -> bool {
+        true
+    }
+
+    /// Line to print on stdout once the service is ready, e.g. `SERVICE_READY`
+    ///
+    /// This lets integration tests which spawn the binary wait for it to come
+    /// up, with or without systemd. `run` prints the line once, flushed
+    /// immediately, before the first `run_once` at which the service is ready
+    /// per `Context::set_ready` and `Application::readiness`. Defaults to
+    /// `None`, which prints nothing.
+    fn ready_marker(&self) -> Option<String> {
+        None
+    }
+
+    /// Keep running when `run` fails to install its logger
+    ///
+    /// That only happens when another logger was installed first, such as
+    /// when the application is embedded in a host which sets up logging
+    /// itself. By default `run` exits rather than run without the logging
+    /// configured here; `run_with` can also be told not to install one.
+    fn allow_no_logger(&self) -> bool {
+        false
+    }
+
+    /// Name of an unprivileged user to switch to after startup
+    ///
+    /// The user is looked up before `Application::new`, and privileges are
+    /// dropped right after it returns. This allows privileged setup, such as
+    /// binding to a low port, to be done in `new`.
+    fn run_as_user(&self) -> Option<String> {
+        None
+    }
+
+    /// Directory to `chroot` into after startup
+    ///
+    /// Process state is changed by `run` in this order: `daemonize` first,
+    /// then `Application::new`, then the chroot, which needs root, then the
+    /// `pid_file` is written, then the switch to `run_as_user`, and finally
+    /// the change to `working_dir`. The user is looked up before the chroot,
+    /// but the `pid_file` is written afterwards and so is relative to the new
+    /// root. Failing to chroot is fatal.
+    fn chroot_dir(&self) -> Option<PathBuf> {
+        None
+    }
+
+    /// Directory to change into once privileges have been dropped
+    ///
+    /// This is interpreted within `chroot_dir` when one is set; see there for
+    /// the ordering. Failing to change directory is fatal.
+    fn working_dir(&self) -> Option<PathBuf> {
+        None
+    }
+
+    /// Address of a StatsD collector to send metrics to over UDP
+    ///
+    /// When set, `run` reports the duration of each `run_once` as the
+    /// `run_once` timer, counts iterations with the `iterations` counter, and
+    /// sets the `last_error` gauge to the Unix time of an error returned from
+    /// `run_once`. Applications can add their own with `Context::timing` and
+    /// `Context::incr`. Defaults to `None`.
+    #[cfg(feature = "metrics-statsd")]
+    fn statsd_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    /// Name to show for the process in `ps` and `top`
+    ///
+    /// On Linux, `run` sets this as the name of the main thread early on,
+    /// which is also what `/proc/self/comm` reports. Names are limited to 15
+    /// bytes, and longer ones are truncated with a warning. Elsewhere this is
+    /// ignored.
+    fn process_name(&self) -> Option<String> {
+        None
+    }
+
+    /// Resource limits to apply at startup, such as `(Resource::NoFile, 65536)`
+    ///
+    /// `run` sets each soft limit before `Application::new`, raising the hard
+    /// limit as well when needed. If that isn't permitted, the soft limit is
+    /// raised as far as the hard limit allows and a warning is logged. Other
+    /// failures are also logged without stopping startup.
+    fn rlimits(&self) -> Vec<(Resource, u64)> {
+        Vec::new()
+    }
+
+    /// Report panics through the logger rather than only on stderr
+    ///
+    /// When true, `run` replaces the panic hook with one logging the message,
+    /// location, and thread at the error level. Unwinding or aborting proceeds
+    /// as usual afterwards. Return false to keep a hook of your own.
+    fn log_panics(&self) -> bool {
+        true
+    }
+
+    /// Ignore `SIGPIPE` so writes to a closed pipe fail instead of killing
+    /// the process
+    ///
+    /// The logger already discards failed writes. Return false to have
+    /// `SIGPIPE` terminate the process, as it does by default outside Rust.
+    fn ignore_sigpipe(&self) -> bool {
+        true
+    }
+
+    /// How long `run` retries `Application::check_dependencies` at startup
+    ///
+    /// Once this passes without a successful check, the process exits with
+    /// `sysexits::UNAVAILABLE`. Defaults to one minute.
+    fn dependency_timeout(&self) -> Duration {
+        Duration::from_secs(60)
+    }
+
+    /// Delay before the first retry of `Application::check_dependencies`
+    ///
+    /// The delay doubles after each failure, up to 30 seconds. Defaults to one
+    /// second.
+    fn dependency_backoff(&self) -> Duration {
+        Duration::from_secs(1)
+    }
+
+    /// Address to serve `Application::metrics` on at `/metrics`
+    ///
+    /// The server runs on its own thread and is closed before
+    /// `Application::shutdown` is called. Defaults to `None`, which disables
+    /// the endpoint.
+    #[cfg(feature = "metrics")]
+    fn metrics_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    /// Address to serve `/healthz` and `/readyz` on
+    ///
+    /// The endpoints respond 200 when the corresponding check is healthy and
+    /// 503 with the reason otherwise. The server runs on its own thread and is
+    /// closed before `Application::shutdown` is called. Defaults to `None`,
+    /// which disables the endpoints.
+    #[cfg(feature = "health")]
+    fn health_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    /// Where to accept operator commands, one per line
+    ///
+    /// Each command is answered with a line, with failures starting with
+    /// `error:`; the connection stays open either way. These are built in:
+    ///
+    /// - `reload`: reload the config as for `SignalAction::Reload`
+    /// - `status`: a line with the service, pid, uptime, iterations, and level
+    /// - `loglevel [LEVEL]`: report or change the base log level
+    ///
+    /// Anything else goes to `Application::handle_control`. Commands are
+    /// answered by the main loop between calls to `run_once`. The listener
+    /// runs on its own thread and is closed before `Application::shutdown` is
+    /// called. Defaults to `None`, which disables the socket.
+    #[cfg(feature = "control")]
+    fn control_socket(&self) -> Option<ControlSocket> {
+        None
+    }
+}
+
+/// A context passed to `Application::run_once`
+///
+/// Gives the application control over when to execute certain operations like
+/// signal handling.
+pub struct Context {
+    pub(crate) signal: Receiver<Signal>,
+    stop: Arc<AtomicBool>,
+    pub(crate) watchdog: Option<systemd::Watchdog>,
+    #[cfg(feature = "metrics-statsd")]
+    pub(crate) statsd: Option<statsd::Client>,
+    clock: Arc<dyn Clock>,
+    started: Instant,
+    iterations: AtomicU64,
+    deferred: Mutex<Vec<Signal>>,
+    paused: AtomicUsize,
+    drain: AtomicBool,
+    ready: AtomicBool,
+    threads: Mutex<Vec<Spawned>>,
+    stop_signal: Mutex<Option<Signal>>,
+    /// Number of signals which requested a stop
+    stop_signals: AtomicUsize,
+    /// When a stop was first deferred, and `stop_signals` at the time
+    deferred_stop: Mutex<Option<(Instant, usize)>>,
+    options: Option<Box<dyn Any + Send + Sync>>,
+    /// Copy of the application's `Options`, for reloading the config
+    loaded_options: Option<Box<dyn Any + Send + Sync>>,
+    last_error: Mutex<Option<Instant>>,
+    last_signal: Mutex<Option<Instant>>,
+    last_work: Mutex<Option<Instant>>,
+    idle: AtomicBool,
+    /// Ready when a server has a request for the main loop to answer
+    wake: Receiver<()>,
+    /// Kept even without servers so that `wake` never disconnects
+    #[cfg_attr(not(any(feature = "metrics", feature = "health", feature = "control")), allow(dead_code))]
+    waker: Waker,
+    info: ServiceInfo,
+}
+
+impl Context {
+    pub(crate) fn new(signal: Receiver<Signal>) -> Context {
+        let (waker, wake) = chan::sync(1);
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let now = clock.now_instant();
+        Context {
+            signal,
+            stop: Arc::new(AtomicBool::new(false)),
+            watchdog: systemd::Watchdog::from_env(now),
+            #[cfg(feature = "metrics-statsd")]
+            statsd: None,
+            clock,
+            started: now,
+            iterations: AtomicU64::new(0),
+            deferred: Mutex::new(Vec::new()),
+            paused: AtomicUsize::new(0),
+            drain: AtomicBool::new(false),
+            ready: AtomicBool::new(true),
+            threads: Mutex::new(Vec::new()),
+            stop_signal: Mutex::new(None),
+            stop_signals: AtomicUsize::new(0),
+            deferred_stop: Mutex::new(None),
+            options: None,
+            loaded_options: None,
+            last_error: Mutex::new(None),
+            last_signal: Mutex::new(None),
+            last_work: Mutex::new(None),
+            idle: AtomicBool::new(false),
+            wake,
+            waker: Waker(waker),
+            info: ServiceInfo::new(logging::identifier()),
+        }
+    }
+
+    /// Create a context whose signals come from the returned sender
+    ///
+    /// No signal handlers are installed, so tests can deliver any signal to
+    /// `poll_signals` without affecting the process.
+    ///
+    /// ```rust
+    /// # extern crate chan_signal;
+    /// # extern crate log;
+    /// # extern crate system_service;
+    /// # use std::borrow::Cow;
+    /// # use std::path::Path;
+    /// use chan_signal::Signal;
+    /// use system_service::*;
+    /// # #[derive(Clone)]
+    /// # struct MyOptions;
+    /// # impl LogOptions for MyOptions {
+    /// #     fn target_filter(&self) -> Vec<String> { vec!["my_app".into()] }
+    /// #     fn max_log_level(&self) -> log::LevelFilter { log::LevelFilter::Info }
+    /// # }
+    /// # impl Options for MyOptions {
+    /// #     fn load() -> Result<Self, OptionsError> { Ok(MyOptions) }
+    /// #     fn config_path(&self) -> Cow<Path> { Cow::Borrowed(Path::new("my_app.toml")) }
+    /// # }
+    /// # struct MyConfig;
+    /// # impl Config for MyConfig {
+    /// #     fn load<O: Options>(_: &O) -> Result<Self, ConfigError> { Ok(MyConfig) }
+    /// # }
+    ///
+    /// struct Recorder {
+    ///     received: Vec<Signal>,
+    /// }
+    ///
+    /// impl Application for Recorder {
+    ///     type Err = String;
+    ///     type Config = MyConfig;
+    ///     type Options = MyOptions;
+    ///
+    ///     fn new(_: MyOptions, _: MyConfig) -> Result<Self, String> {
+    ///         Ok(Recorder { received: Vec::new() })
+    ///     }
+    ///
+    ///     fn run_once(&mut self, context: &Context) -> Result<Stopping, String> {
+    ///         Ok(context.poll_signals(self))
+    ///     }
+    ///
+    ///     fn received_signal(&mut self, signal: Signal) -> Stopping {
+    ///         self.received.push(signal);
+    ///         Stopping::No
+    ///     }
+    /// }
+    ///
+    /// # fn main() {
+    /// let (context, signals) = Context::new_for_test();
+    /// let mut app = Recorder::new(MyOptions, MyConfig).unwrap();
+    ///
+    /// // Every pending signal is handled by a single call.
+    /// signals.send(Signal::USR1);
+    /// signals.send(Signal::USR2);
+    /// context.poll_signals(&mut app);
+    /// assert_eq!(app.received, vec![Signal::USR1, Signal::USR2]);
+    ///
+    /// context.poll_signals(&mut app);
+    /// assert_eq!(app.received.len(), 2);
+    ///
+    /// // Repeats of a signal pending at once are handled only once.
+    /// app.received.clear();
+    /// signals.send(Signal::USR2);
+    /// signals.send(Signal::USR2);
+    /// signals.send(Signal::USR1);
+    /// context.poll_signals(&mut app);
+    /// assert_eq!(app.received, vec![Signal::USR2, Signal::USR1]);
+    ///
+    /// // Signals are held while paused and delivered afterwards.
+    /// app.received.clear();
+    /// {
+    ///     let _paused = context.pause_signals();
+    ///     signals.send(Signal::USR1);
+    ///     context.poll_signals(&mut app);
+    ///     assert!(app.received.is_empty());
+    /// }
+    /// context.poll_signals(&mut app);
+    /// assert_eq!(app.received, vec![Signal::USR1]);
+    /// assert!(!context.stop_requested());
+    ///
+    /// // Signals with a standard action never reach `received_signal`.
+    /// app.received.clear();
+    /// signals.send(Signal::TERM);
+    /// context.poll_signals(&mut app);
+    /// assert!(app.received.is_empty());
+    /// assert!(context.stop_requested());
+    /// # }
+    /// ```
+    #[cfg(feature = "testing")]
+    pub fn new_for_test() -> (Context, Sender<Signal>) {
+        let (tx, rx) = chan::async();
+        (Context::new(rx), tx)
+    }
+
+    /// Time elapsed since `run` started
+    ///
+    /// This is measured with a monotonic clock, so it's unaffected by changes
+    /// to the system time.
+    pub fn uptime(&self) -> Duration {
+        self.now().saturating_duration_since(self.started)
+    }
+
+    /// The clock used for uptime, watchdog pings, and `tick_interval`
+    pub fn clock(&self) -> &dyn Clock {
+        &*self.clock
+    }
+
+    /// The clock, for threads which outlive a borrow of the context
+    pub(crate) fn shared_clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    /// Current monotonic time from `clock`
+    ///
+    /// Prefer this to `Instant::now` in `run_once`, so that tests with a
+    /// `testing::MockClock` control it too.
+    pub fn now(&self) -> Instant {
+        self.clock.now_instant()
+    }
+
+    /// Current wall clock time from `clock`
+    pub fn wall_now(&self) -> SystemTime {
+        self.clock.now_system()
+    }
+
+    /// Replace the clock, typically with a `testing::MockClock`
+    ///
+    /// Uptime and the watchdog interval start over from the new clock's
+    /// current time.
+    #[cfg(feature = "testing")]
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        let now = clock.now_instant();
+        self.started = now;
+        if let Some(ref watchdog) = self.watchdog {
+            watchdog.reset(now);
+        }
+        self.clock = clock;
+    }
+
+    /// Number of calls to `run_once` which have completed
+    pub fn iterations(&self) -> u64 {
+        self.iterations.load(Ordering::Relaxed)
+    }
+
+    /// Whether no call to `run_once` has completed yet
+    ///
+    /// An application which couldn't reach a resource in `new` can try
+    /// again here, having reported itself not ready with `set_ready`.
+    pub fn is_first_iteration(&self) -> bool {
+        self.iterations() == 0
+    }
+
+    /// Mark the service ready or not to serve traffic
+    ///
+    /// While not ready, `/readyz` responds 503 without consulting
+    /// `Application::readiness`. Services start out ready.
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::SeqCst);
+    }
+
+    /// Whether the service is ready, per `set_ready`
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    /// Record that a call to `run_once` has completed
+    pub(crate) fn iteration_completed(&self) {
+        self.iterations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a call to `run_once` returned an error
+    pub(crate) fn error_occurred(&self) {
+        if let Ok(mut last_error) = self.last_error.lock() {
+            *last_error = Some(self.now());
+        }
+    }
+
+    /// Time since `run_once` last returned an error, if it ever has
+    ///
+    /// Useful for reporting recent failures from `Application::health` when
+    /// `Application::on_error` keeps the loop running.
+    pub fn since_last_error(&self) -> Option<Duration> {
+        let last_error = self.last_error.lock().ok().and_then(|e| *e)?;
+        Some(self.now().saturating_duration_since(last_error))
+    }
+
+    /// Time since a signal was last received, if one ever has
+    pub fn elapsed_since_last_signal(&self) -> Option<Duration> {
+        let last_signal = self.last_signal.lock().ok().and_then(|s| *s)?;
+        Some(self.now().saturating_duration_since(last_signal))
+    }
+
+    /// Record that the application did some work, so isn't idle
+    ///
+    /// See `Application::max_idle`.
+    pub fn did_work(&self) {
+        if let Ok(mut last_work) = self.last_work.lock() {
+            *last_work = Some(self.now());
+        }
+    }
+
+    /// Time since the last of `did_work`, a received signal, or the start
+    pub fn idle_time(&self) -> Duration {
+        let last_signal = self.last_signal.lock().ok().and_then(|s| *s);
+        let last_work = self.last_work.lock().ok().and_then(|w| *w);
+        let active = [last_signal, last_work]
+            .iter()
+            .filter_map(|&at| at)
+            .fold(self.started, ::std::cmp::max);
+        self.now().saturating_duration_since(active)
+    }
+
+    /// Record that the main loop is stopping for `Application::max_idle`
+    pub(crate) fn stopped_idle(&self) {
+        self.idle.store(true, Ordering::SeqCst);
+    }
+
+    /// Handle pending signals as `Application::signal_action` says
+    ///
+    /// Only signals whose action is `SignalAction::Custom` are passed to
+    /// `Application::received_signal`. The `LogOptions::reopen_signal` is
+    /// handled here too when there's a log file. Each distinct signal is
+    /// dispatched once per call, in the order it was first received, however
+    /// many times it arrived.
+    ///
+    /// Returns `Stopping::Yes` if any handler asked to stop. In that case the
+    /// main loop also halts once the current `run_once` returns, so it isn't
+    /// strictly necessary to propagate the result.
+    pub fn poll_signals<A: Application>(&self, app: &mut A) -> Stopping {
+        self.dispatch_signals(|s| {
+            if Some(s) == logging::reopen_signal() {
+                logging::reopen_log_file();
+                return Stopping::No;
+            }
+
+            if Some(s) == A::drain_signal() {
+                self.request_drain();
+            }
+
+            match A::signal_action(s) {
+                SignalAction::Shutdown => Stopping::Yes,
+                SignalAction::Reload => {
+                    let _ = reload(app, self, A::reload_config);
+                    Stopping::No
+                },
+                SignalAction::Drain => {
+                    self.request_drain();
+                    Stopping::No
+                },
+                SignalAction::Custom => app.received_signal(s),
+            }
+        })
+    }
+
+    /// Hold signals back from `poll_signals` until the guard is dropped
+    ///
+    /// Signals received in the meantime stay pending, and the first
+    /// `poll_signals` after the guard is dropped delivers them. As always,
+    /// several arrivals of the same signal are then handled once. Pauses may
+    /// be nested, in which case signals are held until every guard is dropped.
+    pub fn pause_signals<'a>(&'a self) -> SignalsPaused<'a> {
+        self.paused.fetch_add(1, Ordering::SeqCst);
+        SignalsPaused { context: self }
+    }
+
+    /// Pass each pending signal to `handler`, recording any request to stop
+    pub(crate) fn dispatch_signals<F>(&self, mut handler: F) -> Stopping
+        where F: FnMut(Signal) -> Stopping
+    {
+        if self.paused.load(Ordering::SeqCst) > 0 {
+            return Stopping::No;
+        }
+
+        let signal = &self.signal;
+
+        // Signals which ended a sleep come first since they arrived earlier.
+        let mut pending = match self.deferred.lock() {
+            Ok(mut deferred) => deferred.split_off(0),
+            Err(_) => Vec::new(),
+        };
+
+        // Collect any and all pending signals.
+        loop {
+            chan_select! {
+                default => { break; },
+                signal.recv() -> sig => {
+                    debug!(target: logging::visible_target(), "Received signal: {:?}", sig);
+                    if let Some(s) = sig {
+                        pending.push(s);
+                    }
+                },
+            }
+        }
+
+        if !pending.is_empty() {
+            if let Ok(mut last_signal) = self.last_signal.lock() {
+                *last_signal = Some(self.now());
+            }
+        }
+
+        // Each kind of signal is handled once per call, in the order first
+        // received, so repeats such as a hammered Ctrl-C don't re-run handlers.
+        let mut stopping = Stopping::No;
+        let mut handled = Vec::with_capacity(pending.len());
+        for s in pending {
+            if handled.contains(&s) {
+                continue;
+            }
+            handled.push(s);
+
+            if let Stopping::Yes = handler(s) {
+                if let Ok(mut stop_signal) = self.stop_signal.lock() {
+                    stop_signal.get_or_insert(s);
+                }
+                self.stop_signals.fetch_add(1, Ordering::SeqCst);
+                stopping = Stopping::Yes;
+            }
+        }
+
+        if let Stopping::Yes = stopping {
+            self.request_stop();
+        }
+
+        stopping
+    }
+
+    /// Sleep for `duration` or until a signal arrives, whichever is first
+    ///
+    /// A signal which ends the sleep is kept for the next `poll_signals`.
+    pub(crate) fn sleep(&self, duration: Duration) {
+        self.sleep_serving(duration, || ());
+    }
+
+    /// Sleep as `sleep` does, calling `serve` whenever a server is waiting
+    /// on the main loop
+    pub(crate) fn sleep_serving<F: FnMut()>(&self, duration: Duration, mut serve: F) {
+        let deadline = self.now() + duration;
+        let signal = &self.signal;
+        let wake = &self.wake;
+
+        loop {
+            let now = self.now();
+            if now >= deadline {
+                return;
+            }
+
+            let timeout = chan::after(deadline - now);
+            chan_select! {
+                timeout.recv() => return,
+                signal.recv() -> sig => {
+                    if let Some(s) = sig {
+                        if let Ok(mut deferred) = self.deferred.lock() {
+                            deferred.push(s);
+                        }
+                    }
+                    return;
+                },
+                wake.recv() => serve(),
+            }
+        }
+    }
+
+    /// For servers to wake the main loop when a request arrives
+    #[cfg(any(feature = "metrics", feature = "health", feature = "control"))]
+    pub(crate) fn waker(&self) -> Waker {
+        self.waker.clone()
+    }
+
+    /// Send a state string to systemd, e.g. `STATUS=...` or `RELOADING=1`
+    ///
+    /// This is a no-op when not running under a `Type=notify` unit. `READY=1`
+    /// is sent automatically once `Application::new` succeeds.
+    pub fn notify(&self, state: &str) -> io::Result<()> {
+        systemd::notify(state)
+    }
+
+    /// Ping the systemd watchdog now
+    ///
+    /// `run` pings automatically between iterations, so a `run_once` which
+    /// takes longer than the `WatchdogSec=` interval is treated as hung. Apps
+    /// that intentionally block for longer should call this periodically.
+    /// This is a no-op if the watchdog isn't enabled.
+    pub fn watchdog_ping(&self) -> io::Result<()> {
+        match self.watchdog {
+            Some(ref watchdog) => watchdog.ping(self.now()),
+            None => Ok(()),
+        }
+    }
+
+    /// Ping the watchdog if it is due
+    pub(crate) fn watchdog_ping_if_due(&self) {
+        if let Some(ref watchdog) = self.watchdog {
+            if let Err(err) = watchdog.ping_if_due(self.now()) {
+                warn!(target: logging::visible_target(), "Failed to ping systemd watchdog: {}", err);
+            }
+        }
+    }
+
+    /// Ask the main loop to halt once the current `run_once` returns
+    ///
+    /// This takes effect regardless of what `run_once` returns, so it can be
+    /// called from deep within application logic without threading a
+    /// `Stopping` back up.
+    pub fn request_stop(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether a stop has been requested by `request_stop` or a signal handler
+    ///
+    /// Long-running work inside `run_once` can poll this to bail out early.
+    pub fn stop_requested(&self) -> bool {
+        self.stop.load(Ordering::SeqCst)
+    }
+
+    /// Whether a requested stop may go ahead, per `Application::can_shutdown`
+    ///
+    /// A stop the application defers is forced by another stop signal, or
+    /// once `Application::shutdown_timeout` has passed since it was deferred.
+    pub(crate) fn stop_allowed<A: Application>(&self, app: &A) -> bool {
+        if app.can_shutdown() {
+            return true;
+        }
+
+        let now = self.now();
+        let signals = self.stop_signals.load(Ordering::SeqCst);
+        let mut deferred = match self.deferred_stop.lock() {
+            Ok(deferred) => deferred,
+            Err(_) => return true,
+        };
+        let target = logging::visible_target();
+
+        match *deferred {
+            None => {
+                info!(
+                    target: target,
+                    "Stop deferred until the application can shut down; signal again to force it",
+                );
+                *deferred = Some((now, signals));
+                false
+            },
+            Some((_, seen)) if signals > seen => {
+                info!(target: target, "Stop forced by a repeated signal");
+                true
+            },
+            Some((since, _)) => match app.shutdown_timeout() {
+                Some(timeout) if now.saturating_duration_since(since) >= timeout => {
+                    warn!(
+                        target: target,
+                        "Stop forced after waiting {:?} for the application",
+                        timeout,
+                    );
+                    true
+                },
+                _ => false,
+            },
+        }
+    }
+
+    /// A handle for observing and requesting a stop from other threads
+    ///
+    /// Threads spawned by the application can't borrow the `Context`, but can
+    /// keep one of these to notice when the service is stopping.
+    pub fn stop_handle(&self) -> StopHandle {
+        StopHandle { stop: self.stop.clone(), observed: self.stop.clone() }
+    }
+
+    /// Spawn a named background thread which `run` joins at shutdown
     ///
-    /// Implementers should use the `die!` macro to handle failure.
-    fn load<O: Options>(_: &O) -> Self;
-}
+    /// Same as `spawn_in_phase` with phase 0, so these threads stop first.
+    pub fn spawn<F>(&self, name: &str, f: F) -> io::Result<()>
+        where F: FnOnce(&StopHandle) + Send + 'static
+    {
+        self.spawn_in_phase(name, 0, f)
+    }
 
-/// Trait required for loading CLI options
-pub trait Options : LogOptions {
-    /// Parse options and return them
+    /// Spawn a named background thread which `run` stops and joins in `phase`
+    ///
+    /// `f` is given a `StopHandle` and should return soon after a stop is
+    /// requested of it. Once `Application::shutdown` returns, `run` goes
+    /// through the phases in ascending order: every thread of a phase is told
+    /// to stop at once, and all of them are waited for before the next phase
+    /// is told. Until then, a thread's `StopHandle::stop_requested` stays
+    /// false even though the main loop has ended, so a later phase can keep
+    /// serving an earlier one. For example, an HTTP listener in phase 0 stops
+    /// before the database pool it uses in phase 1.
     ///
-    /// Implementers should use the `die!` macro to handle failure.
-    fn load() -> Self;
+    /// When `Application::shutdown_timeout` is set, the threads get that long
+    /// in total, on top of the time given to `shutdown`. Any still running
+    /// are detached with a warning rather than waited for.
+    pub fn spawn_in_phase<F>(&self, name: &str, phase: u32, f: F) -> io::Result<()>
+        where F: FnOnce(&StopHandle) + Send + 'static
+    {
+        let observed = Arc::new(AtomicBool::new(false));
+        let handle = StopHandle { stop: self.stop.clone(), observed: observed.clone() };
+        let (done, finished) = mpsc::channel::<()>();
+        let thread = thread::Builder::new()
+            .name(name.to_owned())
+            .spawn(move || {
+                // Dropped on return or unwind, which tells join_threads.
+                let _done = done;
+                f(&handle)
+            })?;
 
-    /// Get the path to a config file
+        if let Ok(mut threads) = self.threads.lock() {
+            threads.push(Spawned { phase, stop: observed, finished, thread });
+        }
+
+        Ok(())
+    }
+
+    /// The options `run` loaded, or what `Application::retain_options` kept
+    ///
+    /// `run` always keeps a copy of the application's `Options`, as merged
+    /// with the config by `Application::merge_config`, so `O` can simply be
+    /// that type. When `retain_options` kept something of type `O`, that is
+    /// returned instead. This is `None` only when `O` is neither.
+    pub fn options<O: Any>(&self) -> Option<&O> {
+        self.options.as_ref()
+            .and_then(|options| options.downcast_ref())
+            .or_else(|| self.loaded_options.as_ref().and_then(|options| options.downcast_ref()))
+    }
+
+    /// What the service is and when it started; see `Application::service_info`
+    pub fn info(&self) -> &ServiceInfo {
+        &self.info
+    }
+
+    /// Replace the info describing the service, keeping its pid and start time
+    pub(crate) fn set_info(&mut self, info: ServiceInfo) {
+        self.info = info.started_as(&self.info);
+    }
+
+    /// Keep a copy of the options `run` loaded, for reloading the config
+    pub(crate) fn keep_options<O: Options>(&mut self, options: &O) {
+        self.loaded_options = Some(Box::new(options.clone()));
+    }
+
+    /// The options kept by `keep_options`
+    pub(crate) fn loaded_options<O: Options>(&self) -> Option<&O> {
+        self.loaded_options.as_ref().and_then(|options| options.downcast_ref())
+    }
+
+    /// Keep options for `options`
+    pub(crate) fn retain_options(&mut self, options: Box<dyn Any + Send + Sync>) {
+        self.options = Some(options);
+    }
+
+    /// Why the main loop stopped, given whether `run_once` returned
+    /// `Stopping::Yes`
+    ///
+    /// A signal which stopped the application takes precedence.
+    pub(crate) fn shutdown_reason(&self, completed: bool) -> ShutdownReason {
+        let signal = self.stop_signal.lock().ok().and_then(|s| *s);
+        match signal {
+            Some(signal) => ShutdownReason::Signal(signal),
+            None if self.idle.load(Ordering::SeqCst) => ShutdownReason::Idle,
+            None if completed => ShutdownReason::Completed,
+            None => ShutdownReason::StopRequested,
+        }
+    }
+
+    /// Request a stop and wait for threads started by `spawn`, phase by phase
+    ///
+    /// Threads still running after `timeout` are detached.
+    pub(crate) fn join_threads(&self, timeout: Option<Duration>) {
+        self.request_stop();
+
+        let mut threads = match self.threads.lock() {
+            Ok(mut threads) => threads.split_off(0),
+            Err(_) => return,
+        };
+
+        // The sort is stable, so each phase is joined in spawning order.
+        threads.sort_by_key(|spawned| spawned.phase);
+        let deadline = timeout.map(|timeout| self.now() + timeout);
+
+        while !threads.is_empty() {
+            let phase = threads[0].phase;
+            let end = threads.iter().position(|s| s.phase != phase).unwrap_or(threads.len());
+            let later = threads.split_off(end);
+
+            for spawned in &threads {
+                spawned.stop.store(true, Ordering::SeqCst);
+            }
+            for spawned in threads {
+                spawned.join(deadline, self.clock());
+            }
+
+            threads = later;
+        }
+    }
+
+    /// Send a StatsD timer when `Options::statsd_addr` is set
+    #[cfg(feature = "metrics-statsd")]
+    pub fn timing(&self, name: &str, duration: Duration) {
+        if let Some(ref statsd) = self.statsd {
+            statsd.timing(name, duration);
+        }
+    }
+
+    /// Increment a StatsD counter when `Options::statsd_addr` is set
+    #[cfg(feature = "metrics-statsd")]
+    pub fn incr(&self, name: &str) {
+        if let Some(ref statsd) = self.statsd {
+            statsd.incr(name);
+        }
+    }
+
+    /// Ask the application to drain, as if `Application::drain_signal` had
+    /// been received
+    pub fn request_drain(&self) {
+        self.drain.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether a drain has been requested by `request_drain` or the drain
+    /// signal
     ///
-    /// This method is needed since Config::load is expected to be generic.
-    fn config_path<'a>(&'a self) -> Cow<'a, Path>;
+    /// Applications which keep running while draining can check this in
+    /// `run_once` to stop taking on new work.
+    pub fn drain_requested(&self) -> bool {
+        self.drain.load(Ordering::SeqCst)
+    }
+
+    /// The base level of the logger installed by `run`
+    ///
+    /// Level overrides from `LogOptions::level_overrides` still apply on top
+    /// of it. When `RUST_LOG` or `LogOptions::log_directives` controls
+    /// filtering this is the global maximum level instead.
+    pub fn log_level(&self) -> log::LevelFilter {
+        logging::log_level().unwrap_or_else(log::max_level)
+    }
+
+    /// Change the base level of the logger installed by `run`
+    ///
+    /// This takes effect immediately, without a restart. It does nothing when
+    /// `RUST_LOG` or `LogOptions::log_directives` controls filtering, or when
+    /// the logger wasn't installed by `run`.
+    pub fn set_log_level(&self, level: log::LevelFilter) {
+        logging::set_log_level(level);
+    }
 }
 
-/// A context passed to `Application::run_once`
-///
-/// Gives the application control over when to execute certain operations like
-/// signal handling.
-pub struct Context {
-    pub(crate) signal: Receiver<Signal>
+/// Shares the stop flag of a `Context`; see `Context::stop_handle`
+#[derive(Debug, Clone)]
+pub struct StopHandle {
+    /// Stops the main loop
+    stop: Arc<AtomicBool>,
+    /// What `stop_requested` reports; the thread's own flag for `spawn`
+    observed: Arc<AtomicBool>,
 }
 
-impl Context {
-    pub fn poll_signals<A: Application>(&self, app: &mut A) {
-        let signal = &self.signal;
+impl StopHandle {
+    /// Same as `Context::request_stop`
+    pub fn request_stop(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+        self.observed.store(true, Ordering::SeqCst);
+    }
 
-        // Handle any and all pending signals.
-        loop {
-            chan_select! {
-                default => { break; },
-                signal.recv() -> sig => {
-                    debug!("Received signal: {:?}", sig);
-                    sig.map(|s| app.received_signal(s));
-                },
+    /// Same as `Context::stop_requested`
+    ///
+    /// For a handle given to a thread by `Context::spawn`, this instead says
+    /// whether that thread's phase has been told to stop, or the thread called
+    /// `request_stop` itself.
+    pub fn stop_requested(&self) -> bool {
+        self.observed.load(Ordering::SeqCst)
+    }
+}
+
+/// Wakes a main loop sleeping in `Context::sleep_serving`
+#[derive(Clone)]
+#[cfg_attr(not(any(feature = "metrics", feature = "health", feature = "control")), allow(dead_code))]
+pub(crate) struct Waker(Sender<()>);
+
+impl Waker {
+    /// Wake the main loop, unless a wake-up is already pending
+    #[cfg(any(feature = "metrics", feature = "health", feature = "control"))]
+    pub(crate) fn wake(&self) {
+        let waker = &self.0;
+        chan_select! {
+            default => {},
+            waker.send(()) => {},
+        }
+    }
+}
+
+/// A thread started by `Context::spawn_in_phase`
+struct Spawned {
+    phase: u32,
+    stop: Arc<AtomicBool>,
+    /// Disconnects once the thread finishes
+    finished: mpsc::Receiver<()>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl Spawned {
+    /// Wait for the thread, detaching it if `deadline` on `clock` passes first
+    fn join(self, deadline: Option<Instant>, clock: &dyn Clock) {
+        let name = self.thread.thread().name().unwrap_or("<unnamed>").to_owned();
+
+        if let Some(deadline) = deadline {
+            let remaining = deadline.saturating_duration_since(clock.now_instant());
+            if let Err(mpsc::RecvTimeoutError::Timeout) = self.finished.recv_timeout(remaining) {
+                warn!(
+                    target: logging::visible_target(),
+                    "Background thread {} did not stop in time; detaching it",
+                    name,
+                );
+                return;
             }
         }
+
+        if self.thread.join().is_err() {
+            warn!(target: logging::visible_target(), "Background thread {} panicked", name);
+        }
+    }
+}
+
+/// Guard returned by `Context::pause_signals`
+pub struct SignalsPaused<'a> {
+    context: &'a Context,
+}
+
+impl<'a> Drop for SignalsPaused<'a> {
+    fn drop(&mut self) {
+        self.context.paused.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Load a fresh config for reloading, logging any failure
+///
+/// The options are those `run` loaded, kept in `context` since
+/// `Application::new` consumes them, so any `Application::merge_config`
+/// changes still apply and the command line isn't parsed again.
+pub(crate) fn fresh_config<A: Application>(context: &Context) -> Option<A::Config> {
+    let target = logging::visible_target();
+    info!(target: target, "Reloading configuration");
+    let opts = match context.loaded_options::<A::Options>() {
+        Some(opts) => opts,
+        None => {
+            error!(target: target, "No options were kept to reload the config with");
+            return None;
+        }
+    };
+
+    match load_config(opts) {
+        Ok(config) => Some(config),
+        Err(err) => {
+            error!(target: target, "Failed to load config for reload: {}", err);
+            None
+        }
+    }
+}
+
+/// Reload the config and give it to the application with `apply`
+///
+/// The config is loaded, environment overrides applied, and
+/// `Config::validate` called, in that order, and only a config which passed
+/// every step reaches `apply`. A failure at any step is logged and returned,
+/// and leaves the application running with the config it had.
+pub(crate) fn reload<A, F>(app: &mut A, context: &Context, apply: F) -> Result<(), String>
+    where A: Application,
+          F: FnOnce(&mut A, A::Config) -> Result<(), A::Err>
+{
+    let config = fresh_config::<A>(context).ok_or("failed to load config; see the log")?;
+    apply(app, config).map_err(|err| {
+        error!(
+            target: logging::visible_target(),
+            "Configuration reload failed; continuing with previous config: {}",
+            err,
+        );
+        format!("reload failed: {}", err)
+    })
+}
+
+/// Load config, apply any environment overrides, and validate it
+pub(crate) fn load_config<C: Config, O: Options>(opts: &O) -> Result<C, ConfigError> {
+    let mut config = C::load(opts)?;
+    if let Some(prefix) = opts.env_prefix() {
+        config.apply_env(&prefix)?;
+    }
+    config.validate()?;
+
+    Ok(config)
+}
+
+/// Validate `config`, or the config loaded for `opts`, and exit with the
+/// result; see `Options::check_only`
+pub(crate) fn check_config<C: Config, O: Options>(opts: &O, config: Option<C>) -> ! {
+    let path = opts.config_path();
+    let result = match config {
+        Some(config) => config.validate(),
+        None => load_config::<C, O>(opts).map(|_| ()),
+    };
+
+    match result {
+        Ok(()) => {
+            println!("Config {} is valid", path.display());
+            process::exit(sysexits::OK);
+        },
+        Err(err) => die_with!(sysexits::CONFIG, "Invalid config {}: {}", path.display(), err),
     }
 }
 
 /// The application; domain-specific program logic
 pub trait Application: Sized {
     /// Main error export of the Application
-    type Err: Send + 'static;
+    ///
+    /// `run` logs the error before returning it, so it must be displayable.
+    type Err: fmt::Display + Send + 'static;
 
     /// Config to be loaded from a file
     type Config: Config;
@@ -86,27 +1442,509 @@ pub trait Application: Sized {
     /// Options from the command line
     type Options: Options;
 
+    /// Fill in options which weren't given from the loaded config
+    ///
+    /// `run` calls this after loading the config and before `new`, so config
+    /// file values can serve as defaults for command line flags. Only merge
+    /// `Option<_>` fields which are still `None`; a flag with a plain default
+    /// can't be told apart from one given explicitly. Options used before the
+    /// config is loaded, such as `daemonize` and the `LogOptions`, aren't
+    /// affected. The default does nothing.
+    ///
+    /// ```rust,ignore
+    /// fn merge_config(options: &mut MyOptions, config: &MyConfig) {
+    ///     if options.pid_file.is_none() {
+    ///         options.pid_file = config.pid_file.clone();
+    ///     }
+    /// }
+    /// ```
+    fn merge_config(_options: &mut Self::Options, _config: &Self::Config) {}
+
+    /// Keep something derived from the options for `Context::options`
+    ///
+    /// `run` already keeps a copy of the options themselves, so this is only
+    /// needed to keep a different type, such as a subset of the options or a
+    /// value computed from them. The default keeps nothing extra.
+    fn retain_options(_options: &Self::Options) -> Option<Box<dyn Any + Send + Sync>> {
+        None
+    }
+
     /// Create a new instance given the options and config
     fn new(_: Self::Options, _: Self::Config) -> Result<Self, Self::Err>;
 
+    /// Called once with the `Context` before the main loop starts
+    ///
+    /// This runs after privileges are dropped and before systemd is notified
+    /// of readiness, so it's the place for setup which needs the context, such
+    /// as handing `Context::stop_handle` to background threads. Returning an
+    /// error aborts `run`. The default does nothing.
+    fn on_start(&mut self, _context: &Context) -> Result<(), Self::Err> {
+        Ok(())
+    }
+
+    /// Check that services the application relies on are reachable
+    ///
+    /// `run` calls this once privileges have been dropped and before
+    /// `on_start`, retrying failures with backoff as configured by
+    /// `Options::dependency_timeout` and `Options::dependency_backoff`. The
+    /// systemd status shows the latest failure meanwhile, and signals are
+    /// handled between attempts. The default always succeeds.
+    fn check_dependencies(&self) -> Result<(), Self::Err> {
+        Ok(())
+    }
+
     /// Called repeatedly in the main loop of the application.
     fn run_once(&mut self, context: &Context) -> Result<Stopping, Self::Err>;
 
+    /// What `run` calls each iteration; defaults to `run_once`
+    ///
+    /// Override this instead to say how long to sleep before the next
+    /// iteration with `Loop::Sleep`, such as until the next scheduled job.
+    /// `run_once` is then never called by `run`, though it still has to be
+    /// implemented; returning `Ok(Stopping::Yes)` is enough. A `Stopping` can
+    /// be converted with `Loop::from`.
+    fn run_step(&mut self, context: &Context) -> Result<Loop, Self::Err> {
+        self.run_once(context).map(Loop::from)
+    }
+
     /// Which signal the application is interested in receiving.
     ///
-    /// By default, only INT and TERM are blocked and handled.
+    /// By default, only INT and TERM are blocked and handled. See
+    /// `signals_for` to choose them based on the options. `run` receives them
+    /// on a thread named `signals`, which forwards them to the `Context`.
     fn signals() -> &'static [Signal] {
         static SIGNALS: &[Signal] = &[Signal::INT, Signal::TERM];
         SIGNALS
     }
 
-    /// Handle a received signal
-    fn received_signal(&mut self, _: Signal) {
-        die!("received_signal default action");
+    /// Which signals to block and handle, given the options
+    ///
+    /// Signals are blocked process-wide, which only works before any other
+    /// thread has started. `run` therefore calls this with the parsed options
+    /// before initializing logging, loading the config, or calling `new`. A
+    /// choice which depends on the config, such as only handling HUP when
+    /// reloading is enabled, needs to be available from the options, perhaps
+    /// by having `Options::load` read that setting. Defaults to `signals`.
+    fn signals_for(_options: &Self::Options) -> Vec<Signal> {
+        Self::signals().to_vec()
+    }
+
+    /// What to do when `signal` is received
+    ///
+    /// Only signals listed in `signals` are received at all, so a signal needs
+    /// to be there as well as here for its action to have any effect; HUP, for
+    /// example, isn't listed by default. By default, INT and TERM shut down,
+    /// HUP reloads the config, and anything else is `SignalAction::Custom`,
+    /// which passes it to `received_signal`. Return `Custom` for a standard
+    /// signal to handle it in `received_signal` instead.
+    fn signal_action(signal: Signal) -> SignalAction {
+        match signal {
+            Signal::INT | Signal::TERM => SignalAction::Shutdown,
+            Signal::HUP => SignalAction::Reload,
+            _ => SignalAction::Custom,
+        }
+    }
+
+    /// A short description of the application for the startup banner
+    ///
+    /// The default is `service_info` as text: the name, then the version and
+    /// build when they're set.
+    fn describe() -> String {
+        Self::service_info().to_string()
+    }
+
+    /// Identity of the application, for `Context::info` and `/info`
+    ///
+    /// `run` fills in the process id and start time. This is a good place for
+    /// a version and git sha, for example
+    /// `ServiceInfo::new("my_app").with_version(env!("CARGO_PKG_VERSION"))`.
+    /// The default is named after the program, with no version.
+    fn service_info() -> ServiceInfo {
+        ServiceInfo::new(logging::identifier())
+    }
+
+    /// Whether `run` polls signals around each `run_once`
+    ///
+    /// Automatic polling makes sure signals are looked at every iteration even
+    /// if `run_once` doesn't, or blocks before it gets to. An application may
+    /// still poll from `run_once` too; each poll only handles the signals which
+    /// arrived since the last one. Stopping on a poll before `run_once` skips
+    /// that iteration. Defaults to `PollMode::Never`.
+    fn auto_poll() -> PollMode {
+        PollMode::Never
+    }
+
+    /// Apply a configuration reloaded in response to a signal
+    ///
+    /// Called from `Context::poll_signals` with a freshly loaded config when a
+    /// signal whose `signal_action` is `SignalAction::Reload` is received,
+    /// which is HUP by default if `signals` includes it. The config has been
+    /// loaded, had environment overrides applied, and passed
+    /// `Config::validate` by then; if any of those fail, this isn't called.
+    ///
+    /// A failure to apply the new config should be surfaced by returning an
+    /// error; this aborts the reload but not the process. The application
+    /// should apply the config all at once or not at all, since it's up to
+    /// this method to undo anything it changed before failing. The
+    /// application then keeps running with whatever config it had before.
+    fn reload_config(&mut self, _: Self::Config) -> Result<(), Self::Err> {
+        Ok(())
+    }
+
+    /// Apply a configuration reloaded because the config file changed
+    ///
+    /// Only called when `Options::watch_config` is enabled. The change is
+    /// noticed between calls to `run_once`. Errors are handled as for
+    /// `reload_config`, which this calls by default.
+    #[cfg(feature = "watch")]
+    fn config_changed(&mut self, new: Self::Config) -> Result<(), Self::Err> {
+        self.reload_config(new)
+    }
+
+    /// Handle a received signal whose `signal_action` is `SignalAction::Custom`
+    ///
+    /// Returning `Stopping::Yes` halts the main loop after the current
+    /// `run_once` and proceeds to `drain` and `shutdown`. By default, INT and
+    /// TERM stop the application, USR1 makes logging one level more verbose,
+    /// USR2 resets the log level, and anything else is ignored. USR1 and USR2
+    /// must be listed in `signals` to be received at all, and USR1 reopens the
+    /// log file instead when there is one; see `LogOptions::reopen_signal`.
+    fn received_signal(&mut self, signal: Signal) -> Stopping {
+        match signal {
+            Signal::INT | Signal::TERM => Stopping::Yes,
+            Signal::USR1 => {
+                logging::raise_log_level();
+                Stopping::No
+            },
+            Signal::USR2 => {
+                logging::reset_log_level();
+                Stopping::No
+            },
+            _ => Stopping::No,
+        }
+    }
+
+    /// Signal which asks the application to drain
+    ///
+    /// Receiving it sets `Context::drain_requested` before the signal is
+    /// handled according to `signal_action`. An application which keeps running on it,
+    /// rather than stopping like the default, can stop accepting new work and
+    /// return `Stopping::Yes` once in-flight work is done. The signal must
+    /// also be included in `signals`. Defaults to TERM.
+    fn drain_signal() -> Option<Signal> {
+        Some(Signal::TERM)
+    }
+
+    /// Stop accepting new work and finish what's in flight
+    ///
+    /// Called by `run` once the main loop has stopped and before `shutdown`,
+    /// while the application can still be borrowed. This is the place to close
+    /// listeners. The default does nothing.
+    fn drain(&mut self) -> Result<(), Self::Err> {
+        Ok(())
     }
 
     /// Called when the application is shutting down
     fn shutdown(self) -> Result<(), Self::Err> {
         Ok(())
     }
+
+    /// Called when the application is shutting down, with the reason why
+    ///
+    /// This is what `run` calls. The default ignores the reason and calls
+    /// `shutdown`, so implement one or the other.
+    fn shutdown_with(self, _reason: ShutdownReason) -> Result<(), Self::Err> {
+        self.shutdown()
+    }
+
+    /// Metrics in the Prometheus text exposition format
+    ///
+    /// Served at `/metrics` when `Options::metrics_addr` is set. Scrapes are
+    /// answered by the main loop between calls to `run_once`.
+    #[cfg(feature = "metrics")]
+    fn metrics(&self) -> String {
+        String::new()
+    }
+
+    /// Liveness check served at `/healthz`
+    #[cfg(feature = "health")]
+    fn health(&self) -> Health {
+        Health::Healthy
+    }
+
+    /// Answer a command from `Options::control_socket` which isn't built in
+    ///
+    /// `command` is the whole line with surrounding whitespace removed. The
+    /// response should be a single line, starting with `error:` on failure.
+    /// By default every command is unknown.
+    #[cfg(feature = "control")]
+    fn handle_control(&mut self, command: &str) -> String {
+        format!("error: unknown command {}", command)
+    }
+
+    /// Readiness check served at `/readyz`
+    ///
+    /// This is only consulted while the service is ready per
+    /// `Context::set_ready`.
+    #[cfg(feature = "health")]
+    fn readiness(&self) -> Health {
+        Health::Healthy
+    }
+
+    /// Whether the application can stop right now
+    ///
+    /// Consulted by `run` when a stop is requested by a signal or
+    /// `Context::request_stop`. Returning false defers the stop, and `run`
+    /// asks again after each `run_once` until this returns true, another stop
+    /// signal arrives, or `shutdown_timeout` passes. Stops decided by
+    /// `run_once` itself aren't deferred. By default the stop goes ahead.
+    fn can_shutdown(&self) -> bool {
+        true
+    }
+
+    /// Maximum time `shutdown` may take before the process is forcibly exited
+    ///
+    /// The deadline is armed once the main loop has decided to stop. If it
+    /// elapses, an error is logged and the process exits with status 124.
+    /// Threads started with `Context::spawn` are then given as long again to
+    /// stop. By default there is no deadline.
+    fn shutdown_timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Cadence at which `run` calls `run_once`
+    ///
+    /// After each iteration, `run` sleeps for whatever remains of the interval
+    /// since `run_once` was called. A signal arriving during the sleep ends it
+    /// early so the next iteration can handle the signal promptly, while
+    /// server requests are answered without ending it. Returning `None`, the
+    /// default, calls `run_once` again immediately, in which case any waiting
+    /// should happen within `run_once`. An iteration which returns
+    /// `Loop::Sleep` from `run_step` sleeps for that long instead.
+    fn tick_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Warn when a single `run_once` call runs longer than this
+    ///
+    /// A thread started by `run` logs a warning with the iteration number and
+    /// elapsed time, repeated each time the timeout passes again. The call
+    /// isn't interrupted. Defaults to `None`, which disables the warnings.
+    fn run_once_soft_timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Stop after this many calls to `run_once`
+    ///
+    /// `run` then drains and shuts down as it would for `Stopping::Yes`, with
+    /// `ShutdownReason::Completed`. This suits bounded batch jobs and tests of
+    /// `run` itself. Defaults to `None`, which runs until told to stop.
+    fn max_iterations(&self) -> Option<u64> {
+        None
+    }
+
+    /// Stop once this long has passed without work or signals
+    ///
+    /// Work is whatever the application reports with `Context::did_work`; see
+    /// `Context::idle_time`. `run` checks after each `run_once`, and once this
+    /// passes drains and shuts down with `ShutdownReason::Idle`. This suits
+    /// ephemeral deployments which scale to zero. Defaults to `None`, which
+    /// never stops for idleness.
+    fn max_idle(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Catch panics from `run_once` instead of letting them end `run`
+    ///
+    /// When true, each call to `run_once` is wrapped in
+    /// `std::panic::catch_unwind` and `on_panic` decides what happens next.
+    /// By default panics propagate as usual.
+    ///
+    /// `run` asserts the application is unwind safe on its behalf. A panic can
+    /// leave the application's state half updated, so only enable this when
+    /// that state is either trivially consistent or repaired by `on_panic`.
+    /// State shared through a `Mutex` may also be poisoned afterwards.
+    fn catch_panics(&self) -> bool {
+        false
+    }
+
+    /// Called after `run_once` panicked, when `catch_panics` is enabled
+    ///
+    /// The panic has already been reported by the panic hook. Return
+    /// `Stopping::No` to keep running, which is the default, or `Stopping::Yes`
+    /// to shut down as if `run_once` had asked to stop.
+    fn on_panic(&mut self) -> Stopping {
+        Stopping::No
+    }
+
+    /// Called when `run_once` returns an error
+    ///
+    /// Return `Stopping::No` to log or otherwise handle the error and keep
+    /// running. The default, `Stopping::Yes`, makes `run` return the error
+    /// right away without calling `drain` or `shutdown`. Either way the time
+    /// is recorded for `Context::since_last_error`.
+    fn on_error(&mut self, _err: &Self::Err) -> Stopping {
+        Stopping::Yes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use chan;
+    use chan_signal::Signal;
+    use log;
+
+    use logging::LogOptions;
+    use super::*;
+
+    #[derive(Clone)]
+    struct NoOptions;
+
+    impl LogOptions for NoOptions {
+        fn target_filter(&self) -> Vec<String> { vec!["recorder".into()] }
+        fn max_log_level(&self) -> log::LevelFilter { log::LevelFilter::Off }
+    }
+
+    impl Options for NoOptions {
+        fn load() -> Result<Self, OptionsError> { Ok(NoOptions) }
+    }
+
+    /// Records every signal, with INT and TERM dispatched instead of stopping
+    struct Recorder {
+        received: Vec<Signal>,
+    }
+
+    impl Application for Recorder {
+        type Err = String;
+        type Config = NoConfig;
+        type Options = NoOptions;
+
+        fn new(_: NoOptions, _: NoConfig) -> Result<Self, String> {
+            Ok(Recorder { received: Vec::new() })
+        }
+
+        fn run_once(&mut self, context: &Context) -> Result<Stopping, String> {
+            Ok(context.poll_signals(self))
+        }
+
+        fn signal_action(signal: Signal) -> SignalAction {
+            match signal {
+                Signal::INT | Signal::TERM => SignalAction::Custom,
+                _ => SignalAction::Shutdown,
+            }
+        }
+
+        fn received_signal(&mut self, signal: Signal) -> Stopping {
+            self.received.push(signal);
+            Stopping::No
+        }
+    }
+
+    #[test]
+    fn poll_signals_dispatches_each_signal_once_in_first_seen_order() {
+        let (signals, rx) = chan::async();
+        let context = Context::new(rx);
+        let mut app = Recorder::new(NoOptions, NoConfig).unwrap();
+
+        signals.send(Signal::INT);
+        signals.send(Signal::INT);
+        signals.send(Signal::TERM);
+        context.poll_signals(&mut app);
+
+        assert_eq!(app.received, vec![Signal::INT, Signal::TERM]);
+        assert!(!context.stop_requested());
+    }
+
+    thread_local! {
+        /// The port the next `Port::load` reads
+        static NEXT_PORT: Cell<u16> = const { Cell::new(80) };
+    }
+
+    struct Port(u16);
+
+    impl Config for Port {
+        fn load<O: Options>(_: &O) -> Result<Port, ConfigError> {
+            Ok(Port(NEXT_PORT.with(|port| port.get())))
+        }
+
+        fn validate(&self) -> Result<(), ConfigError> {
+            if self.0 == 0 {
+                return Err(ConfigError::new("port must not be 0"));
+            }
+            Ok(())
+        }
+    }
+
+    /// Applies a reloaded port, failing for port 1 as if it were taken
+    struct Listener {
+        port: u16,
+        applied: usize,
+    }
+
+    impl Application for Listener {
+        type Err = String;
+        type Config = Port;
+        type Options = NoOptions;
+
+        fn new(_: NoOptions, config: Port) -> Result<Self, String> {
+            Ok(Listener { port: config.0, applied: 0 })
+        }
+
+        fn run_once(&mut self, context: &Context) -> Result<Stopping, String> {
+            Ok(context.poll_signals(self))
+        }
+
+        fn reload_config(&mut self, config: Port) -> Result<(), String> {
+            self.applied += 1;
+            if config.0 == 1 {
+                return Err("port 1 is taken".into());
+            }
+            self.port = config.0;
+            Ok(())
+        }
+    }
+
+    fn reload_to(port: u16) -> (Listener, Result<(), String>) {
+        let (_signals, rx) = chan::async();
+        let mut context = Context::new(rx);
+        context.keep_options(&NoOptions);
+        let mut app = Listener::new(NoOptions, Port(80)).unwrap();
+
+        NEXT_PORT.with(|next| next.set(port));
+        let result = reload(&mut app, &context, Listener::reload_config);
+        (app, result)
+    }
+
+    #[test]
+    fn reload_applies_a_valid_config() {
+        let (app, result) = reload_to(8080);
+        assert_eq!(result, Ok(()));
+        assert_eq!((app.port, app.applied), (8080, 1));
+    }
+
+    #[test]
+    fn reload_never_applies_an_invalid_config() {
+        let (app, result) = reload_to(0);
+        assert!(result.is_err());
+        assert_eq!((app.port, app.applied), (80, 0));
+    }
+
+    #[test]
+    fn reload_returns_the_error_of_a_failed_apply() {
+        let (app, result) = reload_to(1);
+        assert!(result.unwrap_err().contains("port 1 is taken"));
+        assert_eq!((app.port, app.applied), (80, 1));
+    }
+
+    #[test]
+    fn reload_without_kept_options_fails() {
+        let (_signals, rx) = chan::async();
+        let context = Context::new(rx);
+        let mut app = Listener::new(NoOptions, Port(80)).unwrap();
+
+        assert!(reload(&mut app, &context, Listener::reload_config).is_err());
+        assert_eq!(app.applied, 0);
+    }
 }
@@ -0,0 +1,251 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Applications whose main loop runs on a Tokio runtime
+use std::fmt;
+use std::future::{self, Future};
+use std::pin::Pin;
+use std::task::{self, Poll};
+
+use chan;
+use chan_signal::Signal;
+use log;
+use tokio::runtime;
+use tokio::signal::unix::{self, SignalKind};
+
+use application::{self, Config, Context, Options, Stopping};
+use logging::{self, LogOptions};
+use signal;
+use systemd;
+
+/// A boxed future returned by `AsyncApplication` methods
+///
+/// Implementations typically produce one with `Box::pin(async move { .. })`.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+/// An application with asynchronous logic; the async counterpart of
+/// `Application`
+pub trait AsyncApplication: Sized {
+    /// Main error export of the Application
+    ///
+    /// `run_async` logs the error before returning it, so it must be
+    /// displayable.
+    type Err: fmt::Display + Send + 'static;
+
+    /// Config to be loaded from a file
+    type Config: Config;
+
+    /// Options from the command line
+    type Options: Options;
+
+    /// Create a new instance given the options and config
+    ///
+    /// This is called from within the runtime, so tasks may be spawned.
+    fn new(_: Self::Options, _: Self::Config) -> Result<Self, Self::Err>;
+
+    /// Called repeatedly in the main loop of the application.
+    fn run_once<'a>(
+        &'a mut self,
+        context: &'a Context,
+    ) -> BoxFuture<'a, Result<Stopping, Self::Err>>;
+
+    /// Which signal the application is interested in receiving.
+    ///
+    /// By default, only INT and TERM are handled.
+    fn signals() -> &'static [Signal] {
+        static SIGNALS: &[Signal] = &[Signal::INT, Signal::TERM];
+        SIGNALS
+    }
+
+    /// Which signals to handle, given the options; defaults to `signals`
+    ///
+    /// As with `Application::signals_for`, this is called before the config
+    /// is loaded.
+    fn signals_for(_options: &Self::Options) -> Vec<Signal> {
+        Self::signals().to_vec()
+    }
+
+    /// Handle a received signal
+    ///
+    /// Returning `Stopping::Yes` halts the main loop after the current
+    /// `run_once` and proceeds to `shutdown`. By default, INT and TERM stop
+    /// the application and anything else is ignored.
+    fn received_signal(&mut self, signal: Signal) -> Stopping {
+        match signal {
+            Signal::INT | Signal::TERM => Stopping::Yes,
+            _ => Stopping::No,
+        }
+    }
+
+    /// Called when the application is shutting down
+    fn shutdown(self) -> BoxFuture<'static, Result<(), Self::Err>> {
+        Box::pin(future::ready(Ok(())))
+    }
+}
+
+impl Context {
+    /// Dispatch pending signals to `AsyncApplication::received_signal`
+    ///
+    /// The `LogOptions::reopen_signal` reopens the log file instead when
+    /// there is one, as in `poll_signals`. Every other signal goes to
+    /// `received_signal`, since there is no `signal_action` to consult.
+    ///
+    /// Returns `Stopping::Yes` if any handler asked to stop. In that case the
+    /// main loop also halts once the current `run_once` completes.
+    pub fn poll_async_signals<A: AsyncApplication>(&self, app: &mut A) -> Stopping {
+        self.dispatch_signals(|s| {
+            if Some(s) == logging::reopen_signal() {
+                logging::reopen_log_file();
+                return Stopping::No;
+            }
+
+            app.received_signal(s)
+        })
+    }
+}
+
+/// Run an AsyncApplication on a multi-threaded Tokio runtime
+///
+/// Signals are received with `tokio::signal` rather than `chan_signal`, so
+/// no thread is dedicated to waiting on them. Option and config loading,
+/// logging, log file reopening, and systemd notifications are handled as in
+/// [`run`]. These parts of [`run`] are not supported here:
+///
+/// - The process-level setup options such as `Options::daemonize`,
+///   `Options::pid_file`, and `Options::run_as_user`.
+/// - `Application::signal_action`; every handled signal other than the
+///   `LogOptions::reopen_signal` goes to `AsyncApplication::received_signal`.
+/// - Config reloads, whether on HUP, a changed config file, or the control
+///   socket; there is no `reload_config` to apply them with.
+/// - Draining, as with `Application::drain_signal` and `Application::drain`.
+/// - `Application::stop_allowed`; a requested stop always ends the loop.
+/// - `Application::merge_config`; the options are used as loaded.
+///
+/// `Context::options` returns the application's `Options` as in [`run`].
+///
+/// [`run`]: fn.run.html
+pub fn run_async<T>() -> Result<(), T::Err>
+    where T: AsyncApplication
+{
+    let result = run_app::<T>();
+    if let Err(ref err) = result {
+        error!(target: logging::visible_target(), "Application encountered error: {}", err);
+    }
+
+    // Make sure queued log lines are written before the process exits.
+    log::logger().flush();
+
+    result
+}
+
+fn run_app<T>() -> Result<(), T::Err>
+    where T: AsyncApplication
+{
+    logging::bootstrap();
+    let opts = T::Options::load_or_die();
+    if opts.check_only() {
+        application::check_config::<T::Config, _>(&opts, None);
+    }
+
+    let runtime = runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap_or_else(|err| die!("Failed to start Tokio runtime: {}", err));
+    let _entered = runtime.enter();
+
+    let (tx, rx) = chan::async();
+    let mut signals = if opts.manage_signals() { T::signals_for(&opts) } else { Vec::new() };
+    if let Some(reopen) = opts.reopen_signal() {
+        if opts.manage_signals() && opts.log_file().is_some() && !signals.contains(&reopen) {
+            signals.push(reopen);
+        }
+    }
+    for sig in signals {
+        let kind = match signal::raw(sig) {
+            Some(raw) => SignalKind::from_raw(raw),
+            None => die!("Unsupported signal: {:?}", sig),
+        };
+
+        let stream = unix::signal(kind).unwrap_or_else(|err| {
+            die!("Failed to listen for {:?}: {}", sig, err);
+        });
+
+        runtime.spawn(ForwardSignal { stream, signal: sig, tx: tx.clone() });
+    }
+
+    let mut context = Context::new(rx);
+    context.keep_options(&opts);
+
+    if let Err(err) = logging::init(&opts, opts.foreground()) {
+        if !opts.allow_no_logger() {
+            die!("Failed to install logger: {}", err);
+        }
+        eprintln!("Continuing without the configured logger: {}", err);
+    }
+    if opts.log_panics() {
+        logging::log_panics(&opts);
+    }
+    let config = application::load_config(&opts).unwrap_or_else(|err| {
+        die!("Failed to load config {}: {}", opts.config_path().display(), err);
+    });
+
+    let mut app = T::new(opts, config)?;
+
+    if let Err(err) = systemd::notify("READY=1") {
+        warn!(
+            target: logging::visible_target(),
+            "Failed to notify systemd of readiness: {}",
+            err,
+        );
+    }
+
+    loop {
+        let stopping = runtime.block_on(app.run_once(&context))?;
+        context.iteration_completed();
+
+        if let Stopping::Yes = stopping {
+            break;
+        }
+
+        if context.stop_requested() {
+            break;
+        }
+
+        context.watchdog_ping_if_due();
+    }
+
+    runtime.block_on(app.shutdown())
+}
+
+/// Forwards deliveries of a signal onto the channel backing `Context`
+struct ForwardSignal {
+    stream: unix::Signal,
+    signal: Signal,
+    tx: chan::Sender<Signal>,
+}
+
+impl Future for ForwardSignal {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut task::Context) -> Poll<()> {
+        let this = self.get_mut();
+        loop {
+            match this.stream.poll_recv(cx) {
+                // The channel is unbounded, so this never blocks.
+                Poll::Ready(Some(())) => this.tx.send(this.signal),
+                Poll::Ready(None) => return Poll::Ready(()),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
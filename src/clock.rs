@@ -0,0 +1,41 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Sources of the current time
+use std::time::{Instant, SystemTime};
+
+/// A source of monotonic and wall clock time
+///
+/// `Context` reads time through one of these so time-dependent behavior can
+/// be driven by a fake clock in tests.
+pub trait Clock: Send + Sync {
+    /// The current monotonic time
+    fn now_instant(&self) -> Instant;
+
+    /// The current wall clock time
+    fn now_system(&self) -> SystemTime;
+}
+
+/// The real system clocks
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn now_system(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
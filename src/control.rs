@@ -0,0 +1,304 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Line-based control socket for operators
+//!
+//! Each line received is a command, answered by the main loop with a single
+//! line between calls to `Application::run_once` or while it sleeps. As with the HTTP endpoints, a
+//! command which isn't answered within `RESPONSE_TIMEOUT` gets an error.
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use log;
+
+use application::{self, Application, Context, Waker};
+use logging;
+
+/// How long a command waits for the main loop to answer it
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a connection may sit idle before it's closed
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How often the accept loop checks whether to stop
+///
+/// Polling avoids needing a wake-up connection, which may be impossible for a
+/// Unix socket once the process has changed root.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Where the control socket listens; see `Options::control_socket`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlSocket {
+    /// A Unix stream socket at this path
+    ///
+    /// A stale socket left at the path is replaced, and the socket is removed
+    /// again at shutdown. When the process changes root, the socket should be
+    /// inside the new root to be removable; otherwise it's left behind until
+    /// the next start replaces it.
+    Unix(PathBuf),
+
+    /// A TCP socket; this should normally be a loopback address
+    Tcp(SocketAddr),
+}
+
+/// A command waiting on the main loop
+struct Request {
+    command: String,
+    respond: mpsc::Sender<String>,
+}
+
+enum Listener {
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+
+/// A listening control socket
+///
+/// Dropping the server stops accepting connections and joins its thread.
+/// Open connections are answered with an error from then on.
+pub struct Server {
+    stop: Arc<AtomicBool>,
+    requests: Option<mpsc::Receiver<Request>>,
+    thread: Option<thread::JoinHandle<()>>,
+    path: Option<PathBuf>,
+}
+
+impl Server {
+    /// Start listening on `socket`, waking the main loop with `waker` for
+    /// each command
+    pub fn bind(socket: &ControlSocket, waker: Waker) -> io::Result<Server> {
+        let (listener, path) = match *socket {
+            ControlSocket::Unix(ref path) => {
+                if let Ok(metadata) = fs::symlink_metadata(path) {
+                    if metadata.file_type().is_socket() {
+                        fs::remove_file(path)?;
+                    }
+                }
+                let listener = UnixListener::bind(path)?;
+                // Resolved now so a later change of root or directory can't
+                // leave it pointing elsewhere.
+                let path = fs::canonicalize(path).unwrap_or_else(|_| path.clone());
+                (Listener::Unix(listener), Some(path))
+            },
+            ControlSocket::Tcp(addr) => (Listener::Tcp(TcpListener::bind(addr)?), None),
+        };
+
+        match listener {
+            Listener::Unix(ref listener) => listener.set_nonblocking(true)?,
+            Listener::Tcp(ref listener) => listener.set_nonblocking(true)?,
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let (tx, requests) = mpsc::channel();
+
+        let thread = {
+            let stop = stop.clone();
+            thread::Builder::new()
+                .name("control".into())
+                .spawn(move || accept_loop(&listener, &stop, &tx, &waker))?
+        };
+
+        Ok(Server {
+            stop,
+            requests: Some(requests),
+            thread: Some(thread),
+            path,
+        })
+    }
+
+    /// Remove the socket from within `root` at shutdown
+    ///
+    /// This is for a process about to change root to `root`, after which the
+    /// socket's path as bound no longer leads to it. A socket outside `root`
+    /// can't be reached at all then, so it's left for the next start.
+    pub fn set_root(&mut self, root: &Path) {
+        let root = fs::canonicalize(root).unwrap_or_else(|_| root.to_owned());
+        self.path = self.path.take().and_then(|path| match path.strip_prefix(&root) {
+            Ok(inside) => Some(Path::new("/").join(inside)),
+            Err(_) => {
+                warn!(
+                    target: logging::visible_target(),
+                    "Control socket {} is outside {} and won't be removed at shutdown",
+                    path.display(),
+                    root.display(),
+                );
+                None
+            },
+        });
+    }
+
+    /// Answer any commands which are waiting
+    pub fn respond<F>(&self, mut handler: F)
+        where F: FnMut(&str) -> String
+    {
+        if let Some(ref requests) = self.requests {
+            while let Ok(request) = requests.try_recv() {
+                let _ = request.respond.send(handler(&request.command));
+            }
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.requests.take();
+        self.stop.store(true, Ordering::SeqCst);
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+
+        if let Some(ref path) = self.path {
+            match fs::remove_file(path) {
+                Err(ref err) if err.kind() != io::ErrorKind::NotFound => warn!(
+                    target: logging::visible_target(),
+                    "Failed to remove control socket {}: {}; the next start replaces it",
+                    path.display(),
+                    err,
+                ),
+                _ => {},
+            }
+        }
+    }
+}
+
+fn accept_loop(
+    listener: &Listener,
+    stop: &AtomicBool,
+    requests: &mpsc::Sender<Request>,
+    waker: &Waker,
+) {
+    while !stop.load(Ordering::SeqCst) {
+        let accepted = match *listener {
+            Listener::Unix(ref listener) => listener.accept().and_then(|(stream, _)| {
+                stream.set_nonblocking(false)?;
+                stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+                Ok((Box::new(stream.try_clone()?) as Box<dyn Read + Send>,
+                    Box::new(stream) as Box<dyn Write + Send>))
+            }),
+            Listener::Tcp(ref listener) => listener.accept().and_then(|(stream, _)| {
+                stream.set_nonblocking(false)?;
+                stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+                Ok((Box::new(stream.try_clone()?) as Box<dyn Read + Send>,
+                    Box::new(stream) as Box<dyn Write + Send>))
+            }),
+        };
+
+        match accepted {
+            Ok((reader, writer)) => {
+                let requests = requests.clone();
+                let waker = waker.clone();
+                let spawned = thread::Builder::new()
+                    .name("control-connection".into())
+                    .spawn(move || {
+                        if let Err(err) = serve(reader, writer, &requests, &waker) {
+                            debug!(
+                                target: logging::visible_target(),
+                                "Error on control connection: {}",
+                                err,
+                            );
+                        }
+                    });
+                if let Err(err) = spawned {
+                    warn!(
+                        target: logging::visible_target(),
+                        "Failed to start control connection thread: {}",
+                        err,
+                    );
+                }
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(POLL_INTERVAL);
+            },
+            Err(err) => {
+                warn!(
+                    target: logging::visible_target(),
+                    "Error accepting control connection: {}",
+                    err,
+                );
+                thread::sleep(POLL_INTERVAL);
+            },
+        }
+    }
+}
+
+/// Answer each line read from a connection until it's closed
+fn serve(
+    reader: Box<dyn Read + Send>,
+    mut writer: Box<dyn Write + Send>,
+    requests: &mpsc::Sender<Request>,
+    waker: &Waker,
+) -> io::Result<()> {
+    for line in BufReader::new(reader).lines() {
+        let line = line?;
+        let command = line.trim();
+        if command.is_empty() {
+            continue;
+        }
+
+        let (respond, response) = mpsc::channel();
+        let request = Request { command: command.to_owned(), respond };
+        let response = match requests.send(request) {
+            Ok(()) => {
+                waker.wake();
+                response.recv_timeout(RESPONSE_TIMEOUT).unwrap_or_else(|_| {
+                    "error: no response from service".into()
+                })
+            },
+            Err(_) => "error: service is shutting down".into(),
+        };
+
+        writeln!(writer, "{}", response.trim_end())?;
+        writer.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Answer `command` with a built-in, or `Application::handle_control`
+pub fn dispatch<T: Application>(app: &mut T, context: &Context, command: &str) -> String {
+    let mut words = command.split_whitespace();
+    match (words.next().unwrap_or(""), words.next()) {
+        ("reload", None) => match application::reload(app, context, T::reload_config) {
+            Ok(()) => "ok".into(),
+            Err(err) => format!("error: {}", err),
+        },
+        ("status", None) => {
+            let info = context.info();
+            format!(
+                "{} pid={} uptime={}s iterations={} log_level={}",
+                info,
+                info.pid(),
+                context.uptime().as_secs(),
+                context.iterations(),
+                context.log_level(),
+            )
+        },
+        ("loglevel", None) => context.log_level().to_string(),
+        ("loglevel", Some(level)) => match level.parse::<log::LevelFilter>() {
+            Ok(level) if logging::set_log_level(level) => "ok".into(),
+            Ok(_) => "error: the log level can't be changed".into(),
+            Err(_) => format!("error: invalid log level {}", level),
+        },
+        _ => app.handle_control(command),
+    }
+}
@@ -0,0 +1,73 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Classic double-fork daemonization
+use std::io;
+
+/// Detach from the controlling terminal and continue in a grandchild process
+///
+/// Forks, starts a new session, forks again, changes directory to `/`, and
+/// points stdio at `/dev/null`. Only the final child returns from this
+/// function. It must be called before any threads are spawned since only the
+/// calling thread survives a fork.
+#[cfg(unix)]
+pub fn daemonize() -> io::Result<()> {
+    use std::env;
+    use std::fs::OpenOptions;
+    use std::os::unix::io::AsRawFd;
+
+    use libc;
+
+    fork_and_exit_parent()?;
+
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // Forking again guarantees the daemon isn't a session leader, so it can
+    // never reacquire a controlling terminal.
+    fork_and_exit_parent()?;
+
+    env::set_current_dir("/")?;
+
+    let null = OpenOptions::new().read(true).write(true).open("/dev/null")?;
+    for fd in 0..3 {
+        if unsafe { libc::dup2(null.as_raw_fd(), fd) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn fork_and_exit_parent() -> io::Result<()> {
+    use libc;
+
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(()),
+        _ => unsafe { libc::_exit(0) },
+    }
+}
+
+#[cfg(not(unix))]
+pub fn daemonize() -> io::Result<()> {
+    use logging;
+
+    warn!(
+        target: logging::visible_target(),
+        "Daemonizing is not supported on this platform; continuing in foreground",
+    );
+    Ok(())
+}
@@ -0,0 +1,144 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Parsing human-friendly durations for options and config
+use std::error;
+use std::fmt;
+use std::time::Duration;
+
+/// Parse a duration such as `250ms`, `30s`, `5m`, `1h`, or `7d`
+///
+/// The value is a whole number followed by one of the units `ms`, `s`, `m`,
+/// `h`, or `d`, optionally separated by whitespace. A bare number is rejected
+/// since its unit would be a guess. This suits `Options::load`
+/// implementations, and works as a clap `value_parser`, as for the duration
+/// flags of `StandardOptions`.
+///
+/// ```rust
+/// # extern crate system_service;
+/// use std::time::Duration;
+/// use system_service::{parse_duration, DurationError};
+///
+/// # fn main() {
+/// assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+/// assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+/// assert_eq!(parse_duration(" 2 h "), Ok(Duration::from_secs(7200)));
+///
+/// assert_eq!(parse_duration(""), Err(DurationError::Empty));
+/// assert_eq!(parse_duration("30"), Err(DurationError::MissingUnit));
+/// assert_eq!(parse_duration("1.5s"), Err(DurationError::InvalidNumber));
+/// assert_eq!(parse_duration("3w"), Err(DurationError::UnknownUnit("w".into())));
+/// # }
+/// ```
+pub fn parse_duration(s: &str) -> Result<Duration, DurationError> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err(DurationError::Empty);
+    }
+
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = (&s[..split], s[split..].trim_start());
+    if number.is_empty() {
+        return Err(DurationError::InvalidNumber);
+    }
+    if unit.is_empty() {
+        return Err(DurationError::MissingUnit);
+    }
+
+    let number: u64 = number.parse().map_err(|_| DurationError::Overflow)?;
+    let millis_per_unit: u64 = match unit {
+        "ms" => 1,
+        "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        "d" => 24 * 60 * 60 * 1000,
+        _ if unit.starts_with(['.', ',']) => {
+            return Err(DurationError::InvalidNumber);
+        },
+        _ => return Err(DurationError::UnknownUnit(unit.to_owned())),
+    };
+
+    number
+        .checked_mul(millis_per_unit)
+        .map(Duration::from_millis)
+        .ok_or(DurationError::Overflow)
+}
+
+/// Error returned by `parse_duration`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DurationError {
+    /// The input was empty
+    Empty,
+
+    /// The input didn't start with a whole number
+    InvalidNumber,
+
+    /// The number had no unit
+    MissingUnit,
+
+    /// The unit isn't one of `ms`, `s`, `m`, `h`, or `d`
+    UnknownUnit(String),
+
+    /// The duration is too long to represent
+    Overflow,
+}
+
+impl fmt::Display for DurationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DurationError::Empty => f.write_str("empty duration"),
+            DurationError::InvalidNumber => {
+                f.write_str("duration must start with a whole number")
+            },
+            DurationError::MissingUnit => {
+                f.write_str("duration needs a unit: ms, s, m, h, or d")
+            },
+            DurationError::UnknownUnit(ref unit) => {
+                write!(f, "unknown duration unit {:?}; expected ms, s, m, h, or d", unit)
+            },
+            DurationError::Overflow => f.write_str("duration is too long"),
+        }
+    }
+}
+
+impl error::Error for DurationError {}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn number_too_large_for_u64_overflows() {
+        assert_eq!(parse_duration("18446744073709551616ms"), Err(DurationError::Overflow));
+    }
+
+    #[test]
+    fn number_too_large_for_its_unit_overflows() {
+        assert_eq!(parse_duration("18446744073709551615s"), Err(DurationError::Overflow));
+        assert_eq!(parse_duration("213503982335d"), Err(DurationError::Overflow));
+    }
+
+    #[test]
+    fn largest_number_of_milliseconds_parses() {
+        assert_eq!(
+            parse_duration("18446744073709551615ms"),
+            Ok(Duration::from_millis(u64::MAX)),
+        );
+        assert_eq!(
+            parse_duration("213503982334d"),
+            Ok(Duration::from_millis(213503982334 * 24 * 60 * 60 * 1000)),
+        );
+    }
+}
@@ -0,0 +1,246 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! A provided `Config` which deserializes the file at `Options::config_path`
+use std::env;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde_json::{self, Value};
+use serde_yaml;
+use toml;
+
+use application::{Config, ConfigError, Options};
+
+/// Config deserialized from a TOML, YAML, or JSON file
+///
+/// The format is chosen by the extension of `Options::config_path`: `.toml`,
+/// `.yaml` or `.yml`, and `.json` are supported. Use it in an application with
+/// `type Config = FileConfig<MyConfig>;`, and access the inner value through
+/// `Deref` or `into_inner`.
+///
+/// Values can be overridden from the environment by setting
+/// `Options::env_prefix`. A key `url` in a `database` table is overridden by
+/// `<PREFIX>_DATABASE_URL`, for instance. Only keys present in the file can be
+/// overridden; see `apply_env`. String values are taken verbatim and anything
+/// else is parsed as JSON, except that a null value which isn't overridden
+/// with valid JSON is taken as a string.
+#[derive(Debug, Clone)]
+pub struct FileConfig<T> {
+    inner: T,
+    raw: Value,
+}
+
+impl<T: DeserializeOwned> FileConfig<T> {
+    /// Read and deserialize the file at `path`
+    pub fn from_path(path: &Path) -> Result<FileConfig<T>, FileConfigError> {
+        let format = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Format::Toml,
+            Some("yaml") | Some("yml") => Format::Yaml,
+            Some("json") => Format::Json,
+            _ => return Err(FileConfigError::UnknownFormat(path.to_owned())),
+        };
+
+        let contents = fs::read_to_string(path).map_err(|err| {
+            match err.kind() {
+                io::ErrorKind::NotFound => FileConfigError::NotFound(path.to_owned()),
+                _ => FileConfigError::Io(path.to_owned(), err),
+            }
+        })?;
+
+        // Everything is parsed to a JSON value first so it can be kept around
+        // for applying environment overrides.
+        let parsed = match format {
+            Format::Toml => toml::from_str(&contents).map_err(box_error),
+            Format::Yaml => serde_yaml::from_str(&contents).map_err(box_error),
+            Format::Json => serde_json::from_str(&contents).map_err(box_error),
+        };
+
+        let parse_error = |err| FileConfigError::Parse(path.to_owned(), err);
+        let raw: Value = parsed.map_err(parse_error)?;
+        let inner = serde_json::from_value(raw.clone()).map_err(box_error).map_err(parse_error)?;
+
+        Ok(FileConfig { inner, raw })
+    }
+
+    /// Override values with environment variables starting with `prefix`
+    ///
+    /// Only keys present in the file are looked up, so a field the file
+    /// leaves out, such as one filled in by `#[serde(default)]`, can't be set
+    /// from the environment. Give it a value in the file to allow that.
+    ///
+    /// ```rust
+    /// # extern crate system_service;
+    /// # use std::collections::BTreeMap;
+    /// # use std::{env, fs};
+    /// use system_service::FileConfig;
+    ///
+    /// # fn main() {
+    /// let path = env::temp_dir().join(format!("file-config-{}.json", std::process::id()));
+    /// fs::write(&path, r#"{"host": "localhost"}"#).unwrap();
+    /// env::set_var("MY_APP_HOST", "db.internal");
+    /// env::set_var("MY_APP_PORT", "6432");
+    ///
+    /// let mut config = FileConfig::<BTreeMap<String, String>>::from_path(&path).unwrap();
+    /// config.apply_env("my_app").unwrap();
+    ///
+    /// assert_eq!(config["host"], "db.internal");
+    /// assert!(!config.contains_key("port"));
+    /// # fs::remove_file(&path).unwrap();
+    /// # }
+    /// ```
+    pub fn apply_env(&mut self, prefix: &str) -> Result<(), FileConfigError> {
+        let mut raw = self.raw.clone();
+        override_from_env(&mut raw, &mut prefix.to_uppercase())?;
+
+        self.inner = serde_json::from_value(raw.clone())
+            .map_err(|err| FileConfigError::Env(prefix.to_owned(), Box::new(err)))?;
+        self.raw = raw;
+
+        Ok(())
+    }
+
+    /// Unwrap the deserialized config
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: DeserializeOwned> Config for FileConfig<T> {
+    fn load<O: Options>(options: &O) -> Result<Self, ConfigError> {
+        FileConfig::from_path(&options.config_path()).map_err(ConfigError::new)
+    }
+
+    fn apply_env(&mut self, prefix: &str) -> Result<(), ConfigError> {
+        FileConfig::apply_env(self, prefix).map_err(ConfigError::new)
+    }
+}
+
+impl<T> Deref for FileConfig<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T> DerefMut for FileConfig<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+/// Replace leaves of `value` with environment variables named after their path
+///
+/// `name` is the variable name for `value` itself; it's extended in place
+/// while descending and restored before returning.
+pub(crate) fn override_from_env(value: &mut Value, name: &mut String) -> Result<(), FileConfigError> {
+    if let Value::Object(ref mut map) = *value {
+        for (key, child) in map.iter_mut() {
+            let len = name.len();
+            name.push('_');
+            name.extend(key.chars().map(|c| {
+                if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' }
+            }));
+            override_from_env(child, name)?;
+            name.truncate(len);
+        }
+
+        return Ok(());
+    }
+
+    if let Some(var) = env::var_os(&*name) {
+        let var = var.into_string()
+            .map_err(|_| FileConfigError::Env(name.clone(), "not valid unicode".into()))?;
+
+        *value = match *value {
+            Value::String(_) => Value::String(var),
+            // An absent optional value could be a string or anything else.
+            Value::Null => serde_json::from_str(&var).unwrap_or(Value::String(var)),
+            _ => serde_json::from_str(&var)
+                .map_err(|err| FileConfigError::Env(name.clone(), Box::new(err)))?,
+        };
+    }
+
+    Ok(())
+}
+
+enum Format {
+    Toml,
+    Yaml,
+    Json,
+}
+
+fn box_error<E>(err: E) -> Box<dyn error::Error + Send + Sync>
+    where E: error::Error + Send + Sync + 'static
+{
+    Box::new(err)
+}
+
+/// Error loading a `FileConfig`
+#[derive(Debug)]
+pub enum FileConfigError {
+    /// The config file doesn't exist
+    NotFound(PathBuf),
+
+    /// The config file couldn't be read
+    Io(PathBuf, io::Error),
+
+    /// The file extension doesn't map to a supported format
+    UnknownFormat(PathBuf),
+
+    /// The file contents couldn't be deserialized
+    Parse(PathBuf, Box<dyn error::Error + Send + Sync>),
+
+    /// An environment override was invalid
+    Env(String, Box<dyn error::Error + Send + Sync>),
+}
+
+impl fmt::Display for FileConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FileConfigError::NotFound(ref path) => {
+                write!(f, "{} does not exist", path.display())
+            },
+            FileConfigError::Io(ref path, ref err) => {
+                write!(f, "failed to read {}: {}", path.display(), err)
+            },
+            FileConfigError::UnknownFormat(ref path) => {
+                write!(f, "{} has an unsupported extension; expected .toml, .yaml, .yml, or .json",
+                       path.display())
+            },
+            FileConfigError::Parse(ref path, ref err) => {
+                write!(f, "failed to parse {}: {}", path.display(), err)
+            },
+            FileConfigError::Env(ref name, ref err) => {
+                write!(f, "invalid override from {}: {}", name, err)
+            },
+        }
+    }
+}
+
+impl error::Error for FileConfigError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            FileConfigError::Io(_, ref err) => Some(err),
+            FileConfigError::Parse(_, ref err) |
+            FileConfigError::Env(_, ref err) => Some(&**err),
+            _ => None,
+        }
+    }
+}
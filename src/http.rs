@@ -0,0 +1,221 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Minimal HTTP server for operational endpoints
+//!
+//! Connections are accepted on a dedicated thread, but requests are answered
+//! by the main loop between calls to `Application::run_once`, or while it
+//! sleeps, since that's the only place the application can be borrowed. A
+//! request which isn't answered within `RESPONSE_TIMEOUT` gets a 503.
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use application::Waker;
+use logging;
+
+/// How long a connection waits for the main loop to produce a response
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A response to an HTTP request
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    pub fn ok(body: String) -> Response {
+        Response { status: 200, body }
+    }
+
+    pub fn unavailable(body: String) -> Response {
+        Response { status: 503, body }
+    }
+
+    fn not_found() -> Response {
+        Response { status: 404, body: "not found\n".into() }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            404 => "Not Found",
+            503 => "Service Unavailable",
+            _ => "",
+        }
+    }
+}
+
+/// An HTTP request waiting on the main loop
+struct Request {
+    path: String,
+    respond: mpsc::Sender<Response>,
+}
+
+/// A listening HTTP server serving a fixed set of paths
+///
+/// Dropping the server closes the listener and joins its thread.
+pub struct Server {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    requests: Option<mpsc::Receiver<Request>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Server {
+    /// Bind to `addr` and start accepting connections for `paths`
+    ///
+    /// Requests for any other path get a 404 without involving the main loop,
+    /// which is woken with `waker` for the others.
+    pub fn bind(
+        addr: SocketAddr,
+        paths: &'static [&'static str],
+        waker: Waker,
+    ) -> io::Result<Server> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let (tx, requests) = mpsc::channel();
+
+        let thread = {
+            let stop = stop.clone();
+            thread::Builder::new()
+                .name("http".into())
+                .spawn(move || accept_loop(listener, paths, &stop, &tx, &waker))?
+        };
+
+        Ok(Server {
+            addr,
+            stop,
+            requests: Some(requests),
+            thread: Some(thread),
+        })
+    }
+
+    /// Answer any requests which are waiting
+    pub fn respond<F>(&self, mut handler: F)
+        where F: FnMut(&str) -> Response
+    {
+        if let Some(ref requests) = self.requests {
+            while let Ok(request) = requests.try_recv() {
+                let _ = request.respond.send(handler(&request.path));
+            }
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        // Dropping queued requests fails them immediately rather than leaving
+        // their connections waiting out the response timeout.
+        self.requests.take();
+        self.stop.store(true, Ordering::SeqCst);
+
+        // Wake the accept loop so it notices the stop flag.
+        let _ = TcpStream::connect(wake_addr(self.addr));
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn accept_loop(
+    listener: TcpListener,
+    paths: &[&str],
+    stop: &AtomicBool,
+    requests: &mpsc::Sender<Request>,
+    waker: &Waker,
+) {
+    for stream in listener.incoming() {
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+
+        match stream {
+            Ok(stream) => {
+                if let Err(err) = handle(stream, paths, requests, waker) {
+                    debug!(target: logging::visible_target(), "Error handling HTTP request: {}", err);
+                }
+            },
+            Err(err) => {
+                warn!(target: logging::visible_target(), "Error accepting HTTP connection: {}", err);
+            },
+        }
+    }
+}
+
+fn handle(
+    stream: TcpStream,
+    paths: &[&str],
+    requests: &mpsc::Sender<Request>,
+    waker: &Waker,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+
+    // Only the request line matters; the headers are read and discarded.
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or("");
+    let path = path.split('?').next().unwrap_or("");
+
+    let response = if paths.contains(&path) {
+        let (respond, response) = mpsc::channel();
+        let _ = requests.send(Request { path: path.to_owned(), respond });
+        waker.wake();
+        response.recv_timeout(RESPONSE_TIMEOUT).unwrap_or_else(|_| {
+            Response::unavailable("no response from service\n".into())
+        })
+    } else {
+        Response::not_found()
+    };
+
+    let mut stream = reader.into_inner();
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\n\
+         Content-Type: text/plain; charset=utf-8\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\
+         \r\n\
+         {}",
+        response.status,
+        response.reason(),
+        response.body.len(),
+        response.body,
+    )?;
+    stream.flush()
+}
+
+/// Address to connect to in order to reach a listener bound to `addr`
+fn wake_addr(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => {
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), addr.port())
+        },
+        IpAddr::V6(ip) if ip.is_unspecified() => {
+            SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), addr.port())
+        },
+        _ => addr,
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Identity of the running service
+use std::fmt::{self, Write};
+use std::process;
+use std::time::{Instant, SystemTime};
+
+use humantime;
+
+use logging::JsonStr;
+
+/// Features of this crate which were enabled at build time
+static FEATURES: &[&str] = &[
+    #[cfg(feature = "metrics")]
+    "metrics",
+    #[cfg(feature = "metrics-statsd")]
+    "metrics-statsd",
+    #[cfg(feature = "health")]
+    "health",
+    #[cfg(feature = "async")]
+    "async",
+    #[cfg(feature = "serde-config")]
+    "serde-config",
+    #[cfg(feature = "watch")]
+    "watch",
+    #[cfg(feature = "tracing")]
+    "tracing",
+    #[cfg(feature = "testing")]
+    "testing",
+    #[cfg(feature = "clap")]
+    "clap",
+    #[cfg(feature = "control")]
+    "control",
+];
+
+/// What the service is, and when it started
+///
+/// The application describes itself with `Application::service_info`, and
+/// `run` fills in the process id and start time. The result is available from
+/// `Context::info`, and is served as JSON at `/info` by the metrics and health
+/// servers.
+///
+/// ```rust
+/// # extern crate system_service;
+/// use system_service::ServiceInfo;
+///
+/// # fn main() {
+/// let info = ServiceInfo::new("my_app")
+///     .with_version("1.2.0")
+///     .with_build("4f2a9c1")
+///     .with_field("region", "us-east-1");
+///
+/// assert_eq!(info.to_string(), "my_app 1.2.0 (4f2a9c1)");
+/// assert_eq!(info.fields(), &[("region".to_owned(), "us-east-1".to_owned())]);
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ServiceInfo {
+    name: String,
+    version: Option<String>,
+    build: Option<String>,
+    fields: Vec<(String, String)>,
+    pid: u32,
+    started: SystemTime,
+    started_instant: Instant,
+}
+
+impl ServiceInfo {
+    /// Info for a service called `name`, started now
+    ///
+    /// Typically `env!("CARGO_PKG_NAME")`.
+    pub fn new<S: Into<String>>(name: S) -> ServiceInfo {
+        ServiceInfo {
+            name: name.into(),
+            version: None,
+            build: None,
+            fields: Vec::new(),
+            pid: process::id(),
+            started: SystemTime::now(),
+            started_instant: Instant::now(),
+        }
+    }
+
+    /// Set the version, typically `env!("CARGO_PKG_VERSION")`
+    pub fn with_version<S: Into<String>>(mut self, version: S) -> ServiceInfo {
+        self.version = Some(version.into());
+        self
+    }
+
+    /// Set the build identifier, such as a git sha
+    pub fn with_build<S: Into<String>>(mut self, build: S) -> ServiceInfo {
+        self.build = Some(build.into());
+        self
+    }
+
+    /// Add a custom field
+    pub fn with_field<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> ServiceInfo {
+        self.fields.push((key.into(), value.into()));
+        self
+    }
+
+    /// Name of the service
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Version of the service, if set
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_ref().map(|v| &v[..])
+    }
+
+    /// Build identifier of the service, if set
+    pub fn build(&self) -> Option<&str> {
+        self.build.as_ref().map(|b| &b[..])
+    }
+
+    /// Custom fields, in the order added
+    pub fn fields(&self) -> &[(String, String)] {
+        &self.fields
+    }
+
+    /// Process id of the service
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// Wall clock time at which the service started
+    pub fn started(&self) -> SystemTime {
+        self.started
+    }
+
+    /// Monotonic time at which the service started
+    pub fn started_instant(&self) -> Instant {
+        self.started_instant
+    }
+
+    /// Features of this crate enabled in the build
+    pub fn features(&self) -> &'static [&'static str] {
+        FEATURES
+    }
+
+    /// Take the process id and start time from `other`
+    pub(crate) fn started_as(mut self, other: &ServiceInfo) -> ServiceInfo {
+        self.pid = other.pid;
+        self.started = other.started;
+        self.started_instant = other.started_instant;
+        self
+    }
+
+    /// The info as a JSON object, as served at `/info`
+    pub fn to_json(&self) -> String {
+        fn optional(value: Option<&str>) -> String {
+            value.map(|v| JsonStr(v).to_string()).unwrap_or_else(|| "null".into())
+        }
+
+        let mut json = String::new();
+        let _ = write!(
+            json,
+            "{{\"name\":{},\"version\":{},\"build\":{},\"pid\":{},\"started\":\"{}\",\"features\":[",
+            JsonStr(&self.name),
+            optional(self.version()),
+            optional(self.build()),
+            self.pid,
+            humantime::format_rfc3339_seconds(self.started),
+        );
+        for (i, feature) in FEATURES.iter().enumerate() {
+            let sep = if i == 0 { "" } else { "," };
+            let _ = write!(json, "{}{}", sep, JsonStr(feature));
+        }
+        json.push_str("],\"fields\":{");
+        for (i, (key, value)) in self.fields.iter().enumerate() {
+            let sep = if i == 0 { "" } else { "," };
+            let _ = write!(json, "{}{}:{}", sep, JsonStr(key), JsonStr(value));
+        }
+        json.push_str("}}");
+        json
+    }
+}
+
+/// The name, followed by the version and build when set
+impl fmt::Display for ServiceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)?;
+        if let Some(ref version) = self.version {
+            write!(f, " {}", version)?;
+        }
+        if let Some(ref build) = self.build {
+            write!(f, " ({})", build)?;
+        }
+        Ok(())
+    }
+}
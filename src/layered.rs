@@ -0,0 +1,266 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! A provided `Config` which merges defaults with several config files
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use application::{Config, ConfigError, Options};
+use file_config::{self, FileConfig, FileConfigError};
+
+/// Combining a config with one of higher precedence
+///
+/// Implementations should take every value present in `other`, and keep
+/// their own where `other` has none. For a struct, that means merging each
+/// field in turn:
+///
+/// ```rust
+/// # extern crate system_service;
+/// use system_service::Merge;
+///
+/// #[derive(Default)]
+/// struct Database {
+///     url: Option<String>,
+///     pool_size: Option<u32>,
+/// }
+///
+/// impl Merge for Database {
+///     fn merge(&mut self, other: Database) {
+///         self.url.merge(other.url);
+///         self.pool_size.merge(other.pool_size);
+///     }
+/// }
+///
+/// # fn main() {
+/// let mut database = Database { url: Some("postgres://a".into()), pool_size: Some(4) };
+/// database.merge(Database { url: None, pool_size: Some(16) });
+///
+/// assert_eq!(database.url.as_ref().map(|u| &u[..]), Some("postgres://a"));
+/// assert_eq!(database.pool_size, Some(16));
+/// # }
+/// ```
+///
+/// A field whose values should be merged too, rather than replaced whole,
+/// is best left as a struct implementing `Default` and `Merge` itself; see
+/// `merge_nested` for the optional case.
+pub trait Merge {
+    /// Take the values present in `other` over those in `self`
+    fn merge(&mut self, other: Self);
+}
+
+/// `Some` replaces the value, and `None` keeps it
+impl<T> Merge for Option<T> {
+    fn merge(&mut self, other: Option<T>) {
+        if other.is_some() {
+            *self = other;
+        }
+    }
+}
+
+/// Entries of `other` replace those with the same key
+impl<K: Ord, V> Merge for BTreeMap<K, V> {
+    fn merge(&mut self, other: BTreeMap<K, V>) {
+        self.extend(other);
+    }
+}
+
+/// Entries of `other` replace those with the same key
+impl<K: Eq + Hash, V> Merge for HashMap<K, V> {
+    fn merge(&mut self, other: HashMap<K, V>) {
+        self.extend(other);
+    }
+}
+
+/// Merge an optional nested config field by field
+///
+/// Unlike `Option::merge`, a `Some` in `other` is merged into an existing
+/// value rather than replacing it.
+pub fn merge_nested<T: Merge>(value: &mut Option<T>, other: Option<T>) {
+    match (value.as_mut(), other) {
+        (Some(value), Some(other)) => value.merge(other),
+        (None, Some(other)) => *value = Some(other),
+        (_, None) => (),
+    }
+}
+
+/// Config merged from defaults and each file of `Options::config_paths`
+///
+/// `T::default()` comes first, then each file deserialized as by
+/// `FileConfig`, with later files taking precedence; see `Merge`. Files which
+/// don't exist are skipped, so a system-wide file can be optional. For
+/// `None` to mean absent, fields which a file may leave out should be
+/// `Option`s. Use it with `type Config = Layered<MyConfig>;`, and access the
+/// merged value through `Deref` or `into_inner`.
+///
+/// ```rust
+/// # extern crate system_service;
+/// # use std::borrow::Cow;
+/// # use std::collections::BTreeMap;
+/// # use std::fs;
+/// # use std::path::{Path, PathBuf};
+/// # use system_service::*;
+/// #[derive(Clone)]
+/// struct MyOptions {
+///     dir: PathBuf,
+/// }
+///
+/// # impl LogOptions for MyOptions {
+/// #     fn target_filter(&self) -> Vec<String> { vec!["my_app".into()] }
+/// #     fn max_log_level(&self) -> log::LevelFilter { log::LevelFilter::Info }
+/// # }
+/// impl Options for MyOptions {
+/// #   fn load() -> Result<Self, OptionsError> { unimplemented!() }
+///     fn config_path(&self) -> Cow<Path> {
+///         Cow::Owned(self.dir.join("instance.json"))
+///     }
+///
+///     fn config_paths(&self) -> Vec<PathBuf> {
+///         vec![self.dir.join("system.json"), self.dir.join("instance.json")]
+///     }
+/// }
+///
+/// # fn main() {
+/// let dir = std::env::temp_dir().join(format!("layered-{}", std::process::id()));
+/// fs::create_dir_all(&dir).unwrap();
+/// fs::write(dir.join("system.json"), r#"{"host": "db.internal", "port": "5432"}"#).unwrap();
+/// fs::write(dir.join("instance.json"), r#"{"port": "6432"}"#).unwrap();
+///
+/// let options = MyOptions { dir: dir.clone() };
+/// let config = Layered::<BTreeMap<String, String>>::load(&options).unwrap();
+/// assert_eq!(config["host"], "db.internal");
+/// assert_eq!(config["port"], "6432");
+/// # fs::remove_dir_all(&dir).unwrap();
+/// # }
+/// ```
+///
+/// Environment overrides from `Options::env_prefix` are applied last, to the
+/// merged config with its defaults filled in, so they win over every file
+/// and can set values which no file mentions:
+///
+/// ```rust
+/// # extern crate serde;
+/// # extern crate system_service;
+/// # use std::collections::BTreeMap;
+/// # use std::env;
+/// use serde::{Deserialize, Deserializer, Serialize, Serializer};
+/// use system_service::{Layered, Merge};
+///
+/// #[derive(Clone)]
+/// struct Settings(BTreeMap<String, String>);
+///
+/// impl Default for Settings {
+///     fn default() -> Settings {
+///         let mut settings = BTreeMap::new();
+///         settings.insert("log_level".to_owned(), "info".to_owned());
+///         Settings(settings)
+///     }
+/// }
+///
+/// impl Merge for Settings {
+///     fn merge(&mut self, other: Settings) {
+///         self.0.merge(other.0);
+///     }
+/// }
+/// # impl<'de> Deserialize<'de> for Settings {
+/// #     fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Settings, D::Error> {
+/// #         BTreeMap::deserialize(d).map(Settings)
+/// #     }
+/// # }
+/// # impl Serialize for Settings {
+/// #     fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+/// #         self.0.serialize(s)
+/// #     }
+/// # }
+///
+/// # fn main() {
+/// env::set_var("LAYERED_LOG_LEVEL", "debug");
+///
+/// let mut config = Layered::<Settings>::from_paths::<&str>(&[]).unwrap();
+/// config.apply_env("layered").unwrap();
+/// assert_eq!(config.0["log_level"], "debug");
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Layered<T> {
+    inner: T,
+}
+
+impl<T> Layered<T>
+    where T: DeserializeOwned + Serialize + Merge + Default
+{
+    /// Merge the files at `paths` over `T::default()`, skipping missing ones
+    pub fn from_paths<P: AsRef<Path>>(paths: &[P]) -> Result<Layered<T>, FileConfigError> {
+        let mut inner = T::default();
+        for path in paths {
+            match FileConfig::<T>::from_path(path.as_ref()) {
+                Ok(file) => inner.merge(file.into_inner()),
+                Err(FileConfigError::NotFound(_)) => (),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(Layered { inner })
+    }
+
+    /// Override values in the merged config with environment variables
+    /// starting with `prefix`
+    ///
+    /// Every value of the merged config can be overridden, including those
+    /// only set by `T::default()`, except within an `Option` which is `None`.
+    pub fn apply_env(&mut self, prefix: &str) -> Result<(), FileConfigError> {
+        let env_error = |err| FileConfigError::Env(prefix.to_owned(), Box::new(err));
+
+        let mut raw = serde_json::to_value(&self.inner).map_err(env_error)?;
+        file_config::override_from_env(&mut raw, &mut prefix.to_uppercase())?;
+        self.inner = serde_json::from_value(raw).map_err(env_error)?;
+
+        Ok(())
+    }
+
+    /// Unwrap the merged config
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T> Config for Layered<T>
+    where T: DeserializeOwned + Serialize + Merge + Default
+{
+    fn load<O: Options>(options: &O) -> Result<Self, ConfigError> {
+        Layered::from_paths(&options.config_paths()).map_err(ConfigError::new)
+    }
+
+    fn apply_env(&mut self, prefix: &str) -> Result<(), ConfigError> {
+        Layered::apply_env(self, prefix).map_err(ConfigError::new)
+    }
+}
+
+impl<T> Deref for Layered<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T> DerefMut for Layered<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
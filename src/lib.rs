@@ -26,18 +26,23 @@
 //! There are several traits exported here including [`Application`], [`Config`],
 //! [`Options`], and [`LogOptions`]. The two options traits should be implemented
 //! for your CLI option loadind, [`Config`] for your config file loading, and
-//! [`Application`] for your application logic.
+//! [`Application`] for your application logic. The [`prelude`] module
+//! re-exports these along with the other items most services need.
 //!
 //! The primary run method is [`Application::run_once`] which is called over and
 //! over again in a loop. It is provided a [`Context`] type which gives the
-//! application control of when it checks for signals. Any received signals are
-//! passed to [`Application::received_signal`] for handling.
+//! application control of when it checks for signals. Received signals are
+//! handled according to [`Application::signal_action`]: by default INT and
+//! TERM stop the main loop, HUP reloads the config, and the rest are passed to
+//! [`Application::received_signal`], which may ask for the main loop to stop by
+//! returning [`Stopping::Yes`].
 //!
 //! Once [`Application::run_once`] returns [`Stopping::Yes`], the main loop
 //! terminates and invokes [`Application::shutdown`] before exitting.
 //!
 //! [`Application`]: trait.Application.html
 //! [`Application::run_once`]: trait.Application.html#tymethod.run_once
+//! [`Application::signal_action`]: trait.Application.html#method.signal_action
 //! [`Application::received_signal`]: trait.Application.html#tymethod.received_signal
 //! [`Application::shutdown`]: trait.Application.html#tymethod.shutdown
 //! [`Stopping::Yes`]: enum.Stopping.html#variant.Yes
@@ -45,40 +50,197 @@
 //! [`Options`]: trait.Options.html
 //! [`LogOptions`]: trait.LogOptions.html
 //! [`Context`]: struct.Context.html
+//! [`prelude`]: prelude/index.html
 
 #[macro_use] extern crate chan;
 #[macro_use] extern crate log;
 
 extern crate chan_signal;
+#[cfg(feature = "clap")]
+extern crate clap;
 extern crate env_logger;
+extern crate humantime;
+extern crate libc;
+#[cfg(feature = "async")]
+extern crate tokio;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(feature = "tracing")]
+extern crate tracing_log;
+#[cfg(feature = "watch")]
+extern crate notify;
+#[cfg(feature = "serde-config")]
+extern crate serde;
+#[cfg(feature = "serde-config")]
+extern crate serde_json;
+#[cfg(feature = "serde-config")]
+extern crate serde_yaml;
+#[cfg(feature = "serde-config")]
+extern crate toml;
+
+use std::env;
+use std::fmt;
+use std::io::{self, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
 
 /// Print a message to stderr and exit(1)
 #[macro_export]
 macro_rules! die {
-    ($($arg:tt)*) => {{
+    ($($arg:tt)*) => {
+        $crate::die_with!(1, $($arg)*)
+    }
+}
+
+/// Print a message to stderr and exit with the given status
+///
+/// Conventional statuses are provided by the [`sysexits`] module, e.g.
+/// `die_with!(sysexits::CONFIG, "Invalid config: {}", err)`. The global logger
+/// is flushed first, so lines it still buffers aren't lost.
+///
+/// [`sysexits`]: sysexits/index.html
+#[macro_export]
+macro_rules! die_with {
+    ($code:expr, $($arg:tt)*) => {{
+        $crate::flush_logs();
         eprintln!($($arg)*);
-        ::std::process::exit(1);
+        ::std::process::exit($code);
     }}
 }
 
+/// Flush the global logger before exiting; used by `die_with!`
+#[doc(hidden)]
+pub fn flush_logs() {
+    log::logger().flush();
+}
+
 mod application; // general app stuff
+#[cfg(feature = "async")]
+mod asynchronous;
+mod clock;
+#[cfg(feature = "control")]
+mod control;
+mod daemon;
+mod duration;
+#[cfg(feature = "serde-config")]
+mod file_config;
+#[cfg(any(feature = "metrics", feature = "health"))]
+mod http;
+mod info;
+#[cfg(feature = "serde-config")]
+mod layered;
+mod limits;
 mod logging;
+mod pidfile;
+pub mod prelude;
+mod privileges;
+mod process_name;
+mod signal;
+mod stall;
+#[cfg(feature = "metrics-statsd")]
+mod statsd;
+#[cfg(feature = "clap")]
+mod standard_options;
+pub mod sysexits;
+mod systemd;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "watch")]
+mod watch;
 
 pub use application::{
     Application,
     Stopping,
+    Loop,
+    ShutdownReason,
+    PollMode,
+    SignalAction,
     Config,
+    ConfigError,
+    NoConfig,
     Options,
-    Context
+    OptionsError,
+    Context,
+    SignalsPaused,
+    StopHandle,
+    ExitCode
 };
 
-pub use logging::LogOptions;
+#[cfg(feature = "health")]
+pub use application::Health;
+
+#[cfg(feature = "serde-config")]
+pub use file_config::{FileConfig, FileConfigError};
+
+#[cfg(feature = "serde-config")]
+pub use layered::{Layered, Merge, merge_nested};
+
+#[cfg(feature = "control")]
+pub use control::ControlSocket;
+
+#[cfg(feature = "async")]
+pub use asynchronous::{AsyncApplication, BoxFuture, run_async};
+
+pub use clock::{Clock, SystemClock};
+
+pub use duration::{parse_duration, DurationError};
+
+pub use info::ServiceInfo;
+
+pub use chan_signal::Signal;
+
+#[cfg(feature = "clap")]
+pub use standard_options::{StandardOptions, StandardDefaults, BuiltinDefaults};
+
+pub use limits::Resource;
+
+pub use logging::{
+    LogOptions,
+    LogFormat,
+    ColorChoice,
+    Logger,
+    RotatingFileWriter,
+    SyslogTarget,
+    LogFormatter,
+    dropped_log_lines,
+    log_output_broken
+};
 
 /// Run an Application
 ///
 /// This should be called in your `fn main()` with something like the following.
 ///
-/// ```rust
+/// ```rust,no_run
+/// # #[macro_use] extern crate system_service;
+/// # extern crate log;
+/// # use std::borrow::Cow;
+/// # use std::path::Path;
+/// # use system_service::*;
+/// # #[derive(Clone)]
+/// # struct MyOptions;
+/// # impl LogOptions for MyOptions {
+/// #     fn target_filter(&self) -> Vec<String> { vec!["my_app".into()] }
+/// #     fn max_log_level(&self) -> log::LevelFilter { log::LevelFilter::Info }
+/// # }
+/// # impl Options for MyOptions {
+/// #     fn load() -> Result<Self, OptionsError> { Ok(MyOptions) }
+/// #     fn config_path(&self) -> Cow<Path> { Cow::Borrowed(Path::new("my_app.toml")) }
+/// # }
+/// # struct MyConfig;
+/// # impl Config for MyConfig {
+/// #     fn load<O: Options>(_: &O) -> Result<Self, ConfigError> { Ok(MyConfig) }
+/// # }
+/// # struct MyApplication;
+/// # impl Application for MyApplication {
+/// #     type Err = String;
+/// #     type Config = MyConfig;
+/// #     type Options = MyOptions;
+/// #     fn new(_: MyOptions, _: MyConfig) -> Result<Self, String> { Ok(MyApplication) }
+/// #     fn run_once(&mut self, _: &Context) -> Result<Stopping, String> { Ok(Stopping::Yes) }
+/// # }
 /// fn main() {
 ///     if let Err(err) = run::<MyApplication>() {
 ///         die!("Application encountered error: {}", err);
@@ -87,27 +249,720 @@ pub use logging::LogOptions;
 /// ```
 ///
 /// CLI option loading, config loading, signal handling, and etc. are all
-/// initialized automatically on the Application's behalf.
+/// initialized automatically on the Application's behalf. Messages logged
+/// while options and config load are held until the logger is set up, then
+/// written as usual.
+///
+/// Should the application fail, the error is logged on the application's
+/// target before being returned, so `LogOptions::target_filter` lets it
+/// through.
 pub fn run<T>() -> Result<(), T::Err>
     where T: Application
 {
-    let signal = chan_signal::notify(T::signals());
-    let context = Context { signal };
+    logging::bootstrap();
+    log_result(run_app::<T>(T::Options::load_or_die(), None, true))
+}
 
-    let opts = T::Options::load();
+/// Run an Application with options and config supplied by the caller
+///
+/// This is for embedding an application in another process, such as an
+/// integration test, rather than running it from `fn main()`. It behaves like
+/// [`run`] except that `Options::load` and `Config::load` aren't called, and
+/// the global logger is only installed when `init_logging` is true. Pass false
+/// when the embedding process has a logger of its own; see also
+/// `Options::allow_no_logger`. Config reloads on HUP or file changes load
+/// the config as usual, with the options given here.
+///
+/// The error of a failed application is logged as by [`run`]:
+///
+/// ```rust
+/// # extern crate log;
+/// # extern crate system_service;
+/// # use std::io::{self, Write};
+/// # use std::sync::{Arc, Mutex};
+/// # use system_service::*;
+/// #[derive(Clone, Default)]
+/// struct Captured(Arc<Mutex<Vec<u8>>>);
+///
+/// impl Write for Captured {
+///     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+///         self.0.lock().unwrap().write(buf)
+///     }
+///
+///     fn flush(&mut self) -> io::Result<()> {
+///         Ok(())
+///     }
+/// }
+///
+/// #[derive(Clone)]
+/// struct MyOptions(Captured);
+///
+/// impl LogOptions for MyOptions {
+///     fn target_filter(&self) -> Vec<String> { vec!["my_app".into()] }
+///     fn max_log_level(&self) -> log::LevelFilter { log::LevelFilter::Info }
+///
+///     fn writer(&self) -> Option<Box<dyn Write + Send>> {
+///         Some(Box::new(self.0.clone()))
+///     }
+/// }
+/// # impl Options for MyOptions {
+/// #     fn load() -> Result<Self, OptionsError> { unimplemented!() }
+/// # }
+///
+/// struct Failing;
+///
+/// impl Application for Failing {
+///     type Err = String;
+///     type Config = NoConfig;
+///     type Options = MyOptions;
+///
+///     fn new(_: MyOptions, _: NoConfig) -> Result<Self, String> { Ok(Failing) }
+///
+///     fn run_once(&mut self, _: &Context) -> Result<Stopping, String> {
+///         Err("database is gone".into())
+///     }
+/// }
+///
+/// # fn main() {
+/// let captured = Captured::default();
+/// assert!(run_with::<Failing>(MyOptions(captured.clone()), NoConfig, true).is_err());
+///
+/// let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+/// assert!(output.contains("Application encountered error: database is gone"));
+/// # }
+/// ```
+///
+/// [`run`]: fn.run.html
+pub fn run_with<T>(options: T::Options, config: T::Config, init_logging: bool) -> Result<(), T::Err>
+    where T: Application
+{
+    log_result(run_app::<T>(options, Some(config), init_logging))
+}
+
+/// Log the error of a finished application, and flush the logger
+fn log_result<E: fmt::Display>(result: Result<(), E>) -> Result<(), E> {
+    if let Err(ref err) = result {
+        error!(target: logging::visible_target(), "Application encountered error: {}", err);
+    }
+
+    // Make sure queued log lines are written before the process exits.
+    log::logger().flush();
+
+    result
+}
+
+/// Run an application, loading the config unless one is given
+fn run_app<T>(
+    mut opts: T::Options,
+    config: Option<T::Config>,
+    init_logging: bool,
+) -> Result<(), T::Err>
+    where T: Application
+{
+    if opts.check_only() {
+        application::check_config(&opts, config);
+    }
+
+    // Forking only preserves the calling thread, so this needs to happen
+    // before the signal handling thread starts.
+    if opts.daemonize() && !opts.foreground() {
+        if let Err(err) = daemon::daemonize() {
+            die!("Failed to daemonize: {}", err);
+        }
+    }
 
-    let _ = logging::init::<T::Options>(&opts);
-    let config = Config::load(&opts);
+    set_sigpipe(opts.ignore_sigpipe());
+
+    let mut signals = T::signals_for(&opts);
+    if let Some(reopen) = opts.reopen_signal() {
+        if init_logging && opts.log_file().is_some() && !signals.contains(&reopen) {
+            signals.push(reopen);
+        }
+    }
+    // Without signal handling, the sender is held so the receiver never
+    // closes and is never ready.
+    let (_unmanaged, signal) = if opts.manage_signals() {
+        let signal = signal::notify(&signals).unwrap_or_else(|err| {
+            die!("Failed to start handling signals: {}", err);
+        });
+        (None, signal)
+    } else {
+        let (tx, rx) = chan::sync(0);
+        (Some(tx), rx)
+    };
+    let mut context = Context::new(signal);
+    context.set_info(T::service_info());
+
+    let logger_installed = init_logging && match logging::init(&opts, opts.foreground()) {
+        Ok(()) => true,
+        Err(err) if opts.allow_no_logger() => {
+            eprintln!("Continuing without the configured logger: {}", err);
+            false
+        },
+        Err(err) => die!("Failed to install logger: {}", err),
+    };
+    if logger_installed {
+        let config_path = opts.config_path();
+        let config_path = if config_path.as_os_str().is_empty() {
+            "none".into()
+        } else {
+            config_path.to_string_lossy()
+        };
+        info!(
+            target: &logging::app_target(&opts),
+            "Starting {} (pid {}, config {}, log level {})",
+            T::describe(),
+            context.info().pid(),
+            config_path,
+            log::max_level(),
+        );
+    }
+    if opts.log_panics() {
+        logging::log_panics(&opts);
+    }
+    if let Some(name) = opts.process_name() {
+        if let Err(err) = process_name::set(&name) {
+            warn!(
+                target: logging::visible_target(),
+                "Failed to set process name to {}: {}",
+                name,
+                err,
+            );
+        }
+    }
+    for (resource, value) in opts.rlimits() {
+        if let Err(err) = limits::set(resource, value) {
+            warn!(
+                target: logging::visible_target(),
+                "Failed to set {:?} limit to {}: {}",
+                resource,
+                value,
+                err,
+            );
+        }
+    }
+
+    #[cfg(feature = "metrics-statsd")]
+    {
+        context.statsd = opts.statsd_addr().map(|addr| {
+            statsd::Client::new(addr).unwrap_or_else(|err| {
+                die!("Failed to create StatsD client for {}: {}", addr, err);
+            })
+        });
+    }
+    let config = config.unwrap_or_else(|| {
+        application::load_config(&opts).unwrap_or_else(|err| {
+            die!("Failed to load config {}: {}", opts.config_path().display(), err);
+        })
+    });
+    T::merge_config(&mut opts, &config);
+    let pid_path = opts.pid_file().map(|path| path.into_owned());
+    #[cfg(feature = "metrics")]
+    let metrics_addr = opts.metrics_addr();
+    #[cfg(feature = "health")]
+    let health_addr = opts.health_addr();
+    #[cfg(feature = "control")]
+    let control_socket = opts.control_socket();
+    let chroot_dir = opts.chroot_dir();
+    let working_dir = opts.working_dir();
+    let dependency_timeout = opts.dependency_timeout();
+    let dependency_backoff = opts.dependency_backoff();
+    let app_target = logging::app_target(&opts);
+    let mut ready_marker = opts.ready_marker();
+    let run_as = opts.run_as_user().map(|name| {
+        privileges::User::lookup(&name).unwrap_or_else(|err| {
+            die!("Failed to look up user {}: {}", name, err);
+        })
+    });
 
+    #[cfg(feature = "watch")]
+    let config_watcher = if opts.watch_config() && !opts.config_path().as_os_str().is_empty() {
+        let path = opts.config_path();
+        let watcher = watch::ConfigWatcher::new(&path, opts.watch_debounce());
+        Some(watcher.unwrap_or_else(|err| {
+            die!("Failed to watch config file {}: {}", path.display(), err);
+        }))
+    } else {
+        None
+    };
+
+    context.keep_options(&opts);
+    if let Some(options) = T::retain_options(&opts) {
+        context.retain_options(options);
+    }
     let mut app = T::new(opts, config)?;
 
+    let servers = Servers {
+        #[cfg(feature = "metrics")]
+        metrics: metrics_addr.map(|addr| {
+            let paths = &["/metrics", "/info"];
+            http::Server::bind(addr, paths, context.waker()).unwrap_or_else(|err| {
+                die!("Failed to bind metrics server to {}: {}", addr, err);
+            })
+        }),
+
+        #[cfg(feature = "health")]
+        health: health_addr.map(|addr| {
+            let paths = &["/healthz", "/readyz", "/info"];
+            http::Server::bind(addr, paths, context.waker()).unwrap_or_else(|err| {
+                die!("Failed to bind health server to {}: {}", addr, err);
+            })
+        }),
+
+        #[cfg(feature = "control")]
+        control: control_socket.map(|socket| {
+            let mut server = control::Server::bind(&socket, context.waker()).unwrap_or_else(|err| {
+                die!("Failed to bind control socket {:?}: {}", socket, err);
+            });
+            if let Some(ref dir) = chroot_dir {
+                server.set_root(dir);
+            }
+            server
+        }),
+    };
+
+    // Changing root needs the privileges which are about to be dropped.
+    if let Some(dir) = chroot_dir {
+        if let Err(err) = privileges::chroot(&dir) {
+            die!("Failed to chroot to {}: {}", dir.display(), err);
+        }
+    }
+
+    // Written while still privileged, so it can go somewhere like /run.
+    let _pid_file = pid_path.map(|path| {
+        if let Some(pid) = pidfile::running_pid(&path) {
+            die!("Already running with pid {} (see {})", pid, path.display());
+        }
+
+        pidfile::PidFile::create(path.clone()).unwrap_or_else(|err| {
+            die!("Failed to write pid file {}: {}", path.display(), err);
+        })
+    });
+
+    if let Some(user) = run_as {
+        if let Err(err) = user.switch_to() {
+            die!("Failed to drop privileges to user {}: {}", user.name, err);
+        }
+    }
+
+    if let Some(dir) = working_dir {
+        if let Err(err) = env::set_current_dir(&dir) {
+            die!("Failed to change directory to {}: {}", dir.display(), err);
+        }
+    }
+
+    wait_for_dependencies(&mut app, &context, dependency_timeout, dependency_backoff);
+
+    app.on_start(&context)?;
+
+    if let Err(err) = systemd::notify("READY=1") {
+        warn!(
+            target: logging::visible_target(),
+            "Failed to notify systemd of readiness: {}",
+            err,
+        );
+    }
+
+    let auto_poll = T::auto_poll();
+    let stall_monitor = app.run_once_soft_timeout().map(|timeout| {
+        let clock = context.shared_clock();
+        stall::StallMonitor::spawn(timeout, app_target.clone(), clock).unwrap_or_else(|err| {
+            die!("Failed to start stall monitor: {}", err);
+        })
+    });
+
+    // Whether run_once itself ended the loop, for the shutdown reason.
+    let completed = loop {
+        // A stop may already have been requested during startup.
+        if context.stop_requested() && context.stop_allowed(&app) {
+            break false;
+        }
+
+        if ready_marker.is_some() && context.is_ready() {
+            #[cfg(feature = "health")]
+            let ready = app.readiness() == Health::Healthy;
+            #[cfg(not(feature = "health"))]
+            let ready = true;
+
+            if ready {
+                if let Some(marker) = ready_marker.take() {
+                    announce_ready(&marker);
+                }
+            }
+        }
+
+        if auto_poll == PollMode::Before || auto_poll == PollMode::Both {
+            if let Stopping::Yes = context.poll_signals(&mut app) {
+                if context.stop_allowed(&app) {
+                    break false;
+                }
+            }
+        }
+
+        let iteration_started = context.now();
+
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!(
+            "run_once",
+            iteration = context.iterations() + 1,
+            uptime = ?context.uptime(),
+        ).entered();
+
+        if let Some(ref monitor) = stall_monitor {
+            monitor.arm(context.iterations() + 1);
+        }
+
+        let result = if app.catch_panics() {
+            match panic::catch_unwind(AssertUnwindSafe(|| app.run_step(&context))) {
+                Ok(result) => result,
+                Err(_) => Ok(Loop::from(app.on_panic())),
+            }
+        } else {
+            app.run_step(&context)
+        };
+
+        #[cfg(feature = "tracing")]
+        drop(span);
+
+        if let Some(ref monitor) = stall_monitor {
+            monitor.disarm();
+        }
+
+        #[cfg(feature = "metrics-statsd")]
+        {
+            if let Some(ref statsd) = context.statsd {
+                let now = context.now();
+                statsd.timing("run_once", now.saturating_duration_since(iteration_started));
+                statsd.incr("iterations");
+                if result.is_err() {
+                    statsd.gauge("last_error", unix_time(context.wall_now()));
+                }
+            }
+        }
+
+        let (stopping, sleep) = match result {
+            Ok(Loop::Stop) => (Stopping::Yes, None),
+            Ok(Loop::Continue) => (Stopping::No, None),
+            Ok(Loop::Sleep(duration)) => (Stopping::No, Some(duration)),
+            Err(err) => {
+                context.error_occurred();
+                match app.on_error(&err) {
+                    Stopping::Yes => return Err(err),
+                    Stopping::No => (Stopping::No, None),
+                }
+            },
+        };
+        context.iteration_completed();
+
+        if auto_poll == PollMode::After || auto_poll == PollMode::Both {
+            context.poll_signals(&mut app);
+        }
+
+        if let Stopping::Yes = stopping {
+            break true;
+        }
+
+        if let Some(max) = app.max_iterations() {
+            if context.iterations() >= max {
+                break true;
+            }
+        }
+
+        if let Some(max_idle) = app.max_idle() {
+            let idle = context.idle_time();
+            if idle >= max_idle {
+                info!(target: &app_target, "Stopping after being idle for {:?}", idle);
+                context.stopped_idle();
+                break true;
+            }
+        }
+
+        if context.stop_requested() && context.stop_allowed(&app) {
+            break false;
+        }
+
+        context.watchdog_ping_if_due();
+
+        #[cfg(feature = "watch")]
+        {
+            if let Some(ref watcher) = config_watcher {
+                if watcher.take_changed() {
+                    let _ = application::reload(&mut app, &context, T::config_changed);
+
+                    // Ignore anything noticed while reloading.
+                    watcher.clear();
+                }
+            }
+        }
+
+        servers.respond(&mut app, &context);
+
+        let sleep = sleep.or_else(|| {
+            let interval = app.tick_interval()?;
+            let elapsed = context.now().saturating_duration_since(iteration_started);
+            interval.checked_sub(elapsed)
+        });
+        if let Some(duration) = sleep {
+            context.sleep_serving(duration, || servers.respond(&mut app, &context));
+        }
+    };
+
+    drop(stall_monitor);
+    #[cfg(any(feature = "metrics", feature = "health", feature = "control"))]
+    drop(servers);
+    #[cfg(feature = "watch")]
+    drop(config_watcher);
+
+    let drain_started = context.now();
+    app.drain()?;
+
+    let timeout = app.shutdown_timeout();
+    let shutdown_started = context.now();
+    let result = {
+        let _deadline = timeout.map(ShutdownDeadline::arm);
+        app.shutdown_with(context.shutdown_reason(completed))
+    };
+    let join_started = context.now();
+    context.join_threads(timeout);
+    let finished = context.now();
+
+    let timings = ShutdownTimings {
+        drain: shutdown_started.saturating_duration_since(drain_started),
+        shutdown: join_started.saturating_duration_since(shutdown_started),
+        join: finished.saturating_duration_since(join_started),
+    };
+    info!(target: &app_target, "{}", timings);
+
+    #[cfg(feature = "metrics-statsd")]
+    {
+        if let Some(ref statsd) = context.statsd {
+            statsd.timing("shutdown.drain", timings.drain);
+            statsd.timing("shutdown.shutdown", timings.shutdown);
+            statsd.timing("shutdown.join", timings.join);
+        }
+    }
+
+    result
+}
+
+/// Servers whose requests are answered by the main loop
+struct Servers {
+    #[cfg(feature = "metrics")]
+    metrics: Option<http::Server>,
+    #[cfg(feature = "health")]
+    health: Option<http::Server>,
+    #[cfg(feature = "control")]
+    control: Option<control::Server>,
+}
+
+impl Servers {
+    /// Answer any requests which are waiting
+    #[cfg_attr(not(any(feature = "metrics", feature = "health", feature = "control")), allow(unused_variables))]
+    fn respond<T: Application>(&self, app: &mut T, context: &Context) {
+        #[cfg(feature = "metrics")]
+        {
+            if let Some(ref server) = self.metrics {
+                server.respond(|path| match path {
+                    "/info" => http::Response::ok(context.info().to_json()),
+                    _ => http::Response::ok(app.metrics()),
+                });
+            }
+        }
+
+        #[cfg(feature = "health")]
+        {
+            if let Some(ref server) = self.health {
+                server.respond(|path| {
+                    let health = match path {
+                        "/info" => return http::Response::ok(context.info().to_json()),
+                        "/readyz" if !context.is_ready() => {
+                            Health::Unhealthy("not ready".into())
+                        },
+                        "/readyz" => app.readiness(),
+                        _ => app.health(),
+                    };
+
+                    match health {
+                        Health::Healthy => http::Response::ok("ok\n".into()),
+                        Health::Unhealthy(reason) => {
+                            http::Response::unavailable(format!("{}\n", reason))
+                        },
+                    }
+                });
+            }
+        }
+
+        #[cfg(feature = "control")]
+        {
+            if let Some(ref server) = self.control {
+                server.respond(|command| control::dispatch(app, context, command));
+            }
+        }
+    }
+}
+
+/// Time spent in each phase of stopping, for tuning `shutdown_timeout`
+struct ShutdownTimings {
+    drain: Duration,
+    shutdown: Duration,
+    join: Duration,
+}
+
+/// Formatted as `shutdown drain=120ms shutdown=800ms join=50ms`
+impl fmt::Display for ShutdownTimings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "shutdown drain={}ms shutdown={}ms join={}ms",
+            self.drain.as_millis(),
+            self.shutdown.as_millis(),
+            self.join.as_millis(),
+        )
+    }
+}
+
+/// Longest delay between retries of `Application::check_dependencies`
+const MAX_DEPENDENCY_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Retry `Application::check_dependencies` until it succeeds, exiting with
+/// `sysexits::UNAVAILABLE` once `timeout` has passed
+///
+/// Signals are handled between attempts. Should one stop the application,
+/// this returns early and the main loop is skipped.
+fn wait_for_dependencies<T>(app: &mut T, context: &Context, timeout: Duration, backoff: Duration)
+    where T: Application
+{
+    let deadline = context.now() + timeout;
+    let mut delay = backoff;
+
     loop {
-        if let Stopping::Yes = app.run_once(&context)? {
-            break;
+        let err = match app.check_dependencies() {
+            Ok(()) => return,
+            Err(err) => err,
+        };
+
+        let now = context.now();
+        if now >= deadline {
+            error!(
+                target: logging::visible_target(),
+                "Dependencies still unavailable after {:?}: {}",
+                timeout,
+                err,
+            );
+            die_with!(sysexits::UNAVAILABLE, "Dependencies unavailable: {}", err);
+        }
+
+        warn!(
+            target: logging::visible_target(),
+            "Dependencies unavailable, retrying in {:?}: {}",
+            delay,
+            err,
+        );
+        let _ = systemd::notify(&format!("STATUS=Waiting for dependencies: {}", err));
+
+        context.sleep(std::cmp::min(delay, deadline - now));
+        if let Stopping::Yes = context.poll_signals(app) {
+            return;
         }
+
+        delay = std::cmp::min(delay * 2, MAX_DEPENDENCY_BACKOFF);
+    }
+}
+
+/// Print `Options::ready_marker` on stdout, bypassing any buffering
+fn announce_ready(marker: &str) {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    if let Err(err) = writeln!(stdout, "{}", marker).and_then(|_| stdout.flush()) {
+        warn!(target: logging::visible_target(), "Failed to print ready marker: {}", err);
     }
+}
 
-    app.shutdown()?;
+/// Ignore `SIGPIPE`, or restore its default disposition
+///
+/// The Rust runtime ignores it before `main`, so the default has to be put
+/// back explicitly.
+fn set_sigpipe(ignore: bool) {
+    let handler = if ignore { libc::SIG_IGN } else { libc::SIG_DFL };
+    unsafe {
+        libc::signal(libc::SIGPIPE, handler);
+    }
+}
 
-    Ok(())
+/// Seconds from the Unix epoch to `time`
+#[cfg(feature = "metrics-statsd")]
+fn unix_time(time: std::time::SystemTime) -> u64 {
+    use std::time::UNIX_EPOCH;
+
+    time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Exit status used when `Application::shutdown` exceeds its deadline
+const SHUTDOWN_TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// Force-exits the process if it isn't dropped before a timeout elapses
+///
+/// Dropping the deadline disarms it and joins the watcher thread.
+struct ShutdownDeadline {
+    disarm: Option<mpsc::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl ShutdownDeadline {
+    fn arm(timeout: Duration) -> ShutdownDeadline {
+        let (disarm, disarmed) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || {
+            if let Err(mpsc::RecvTimeoutError::Timeout) = disarmed.recv_timeout(timeout) {
+                error!(
+                    target: logging::visible_target(),
+                    "Shutdown did not complete within {:?}; exiting",
+                    timeout,
+                );
+                log::logger().flush();
+                process::exit(SHUTDOWN_TIMEOUT_EXIT_CODE);
+            }
+        });
+
+        ShutdownDeadline {
+            disarm: Some(disarm),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for ShutdownDeadline {
+    fn drop(&mut self) {
+        // Hanging up the channel wakes the watcher thread immediately.
+        self.disarm.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Run an Application and return a process exit code
+///
+/// Like [`run`], but maps `Ok` to 0 and an error to its
+/// [`ExitCode::exit_code`], after `run` has logged it. This should be called
+/// in your `fn main()` with something like the following.
+///
+/// ```rust,ignore
+/// fn main() {
+///     ::std::process::exit(run_with_code::<MyApplication>());
+/// }
+/// ```
+///
+/// [`run`]: fn.run.html
+/// [`ExitCode::exit_code`]: trait.ExitCode.html#method.exit_code
+pub fn run_with_code<T>() -> i32
+    where T: Application,
+          T::Err: ExitCode
+{
+    match run::<T>() {
+        Ok(()) => 0,
+        Err(err) => err.exit_code(),
+    }
 }
@@ -0,0 +1,106 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Resource limits applied at startup
+use std::io;
+
+use libc;
+
+use logging;
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type RawResource = libc::__rlimit_resource_t;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+type RawResource = libc::c_int;
+
+/// A resource which can be limited with `Options::rlimits`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    /// Maximum size of a core dump in bytes (`RLIMIT_CORE`)
+    Core,
+
+    /// Maximum size of the data segment in bytes (`RLIMIT_DATA`)
+    Data,
+
+    /// Maximum size of a file the process may create in bytes
+    /// (`RLIMIT_FSIZE`)
+    FileSize,
+
+    /// One more than the largest file descriptor number (`RLIMIT_NOFILE`)
+    NoFile,
+
+    /// Maximum number of processes for the user (`RLIMIT_NPROC`)
+    NProc,
+
+    /// Maximum size of the stack in bytes (`RLIMIT_STACK`)
+    Stack,
+
+    /// Maximum size of the virtual address space in bytes (`RLIMIT_AS`)
+    AddressSpace,
+}
+
+impl Resource {
+    fn raw(self) -> RawResource {
+        match self {
+            Resource::Core => libc::RLIMIT_CORE,
+            Resource::Data => libc::RLIMIT_DATA,
+            Resource::FileSize => libc::RLIMIT_FSIZE,
+            Resource::NoFile => libc::RLIMIT_NOFILE,
+            Resource::NProc => libc::RLIMIT_NPROC,
+            Resource::Stack => libc::RLIMIT_STACK,
+            Resource::AddressSpace => libc::RLIMIT_AS,
+        }
+    }
+}
+
+/// Set the soft limit for `resource` to `value`
+///
+/// The hard limit is raised too when it's lower. Should that not be
+/// permitted, a warning is logged and the soft limit is raised as far as the
+/// hard limit allows instead.
+pub fn set(resource: Resource, value: u64) -> io::Result<()> {
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    if unsafe { libc::getrlimit(resource.raw(), &mut limit) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let value = value as libc::rlim_t;
+    let wanted = libc::rlimit {
+        rlim_cur: value,
+        rlim_max: if limit.rlim_max < value { value } else { limit.rlim_max },
+    };
+
+    if unsafe { libc::setrlimit(resource.raw(), &wanted) } == 0 {
+        return Ok(());
+    }
+
+    let err = io::Error::last_os_error();
+    if err.raw_os_error() != Some(libc::EPERM) || wanted.rlim_max == limit.rlim_max {
+        return Err(err);
+    }
+
+    warn!(
+        target: logging::visible_target(),
+        "Not permitted to raise the hard limit of {:?} to {}; using {}",
+        resource,
+        value,
+        limit.rlim_max,
+    );
+
+    let capped = libc::rlimit { rlim_cur: limit.rlim_max, rlim_max: limit.rlim_max };
+    if unsafe { libc::setrlimit(resource.raw(), &capped) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
@@ -11,10 +11,65 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
-use std::io;
-use std::sync;
+use std::env;
+use std::fmt;
+use std::io::{self, IsTerminal};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::{self, Arc};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
+use chan_signal::Signal;
+use env_logger::filter;
+use humantime;
+use libc;
 use log;
+#[cfg(feature = "tracing")]
+use tracing;
+#[cfg(feature = "tracing")]
+use tracing_log;
+
+use clock::{Clock, SystemClock};
+
+mod bootstrap;
+mod dedup;
+mod journald;
+mod queue;
+mod rotate;
+mod syslog;
+
+pub use self::rotate::RotatingFileWriter;
+pub use self::syslog::SyslogTarget;
+
+/// Format of lines written by the built-in logger
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Plain text lines, optionally prefixed with a systemd level
+    ///
+    /// Key-value pairs of a record follow the message as `key=value`.
+    Text,
+
+    /// One JSON object per line with `level`, `target`, `message`, and
+    /// `timestamp` fields
+    ///
+    /// Key-value pairs of a record are added as fields of their own.
+    Json,
+}
+
+/// Whether text lines are colored by level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Color output when stdout is a terminal
+    Auto,
+
+    /// Always color output, even when not writing to a terminal
+    Always,
+
+    /// Never color output
+    Never,
+}
 
 /// Additional requirents for CLI options to initialize the logging subsystem
 pub trait LogOptions {
@@ -25,110 +80,1495 @@ pub trait LogOptions {
         false
     }
 
-    /// Only messages with this prefix will be filtered. For instance, if the
-    /// package name is "foobar", returning a string "foobar" here will cause
-    /// only messages from the main package to be emitted.
+    /// Record Info messages at the syslog Notice level instead of Info
+    ///
+    /// Info messages map to `<6>` by default, as specified by RFC 5424.
+    /// Returning true restores the older `<5>` mapping.
+    fn info_as_notice(&self) -> bool {
+        false
+    }
+
+    /// Only messages with one of these target prefixes will be emitted. For
+    /// instance, if the package name is "foobar", returning `["foobar"]` here
+    /// will cause only messages from the main package (including modules such
+    /// as `foobar::db`) to be emitted.
+    ///
+    /// Several prefixes may be returned to allow logging from multiple
+    /// first-party crates, e.g. `["foobar", "foobar_db", "foobar_net"]`. A
+    /// record is emitted if its target starts with any of them.
     fn target_filter(&self) -> Vec<String>;
 
+    /// Target prefixes to mute even though they match `target_filter`
+    ///
+    /// A record is emitted if its target starts with an included prefix and
+    /// none of these. Defaults to none.
+    ///
+    /// ```rust
+    /// # extern crate log;
+    /// # extern crate system_service;
+    /// # use std::io::{self, Write};
+    /// # use std::sync::{Arc, Mutex};
+    /// # use log::{Level, Log, Record};
+    /// # use system_service::{LogOptions, Logger};
+    /// # #[derive(Clone, Default)]
+    /// # struct Buffer(Arc<Mutex<Vec<u8>>>);
+    /// # impl Write for Buffer {
+    /// #     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    /// #         self.0.lock().unwrap().write(buf)
+    /// #     }
+    /// #     fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    /// # }
+    /// struct Options;
+    ///
+    /// impl LogOptions for Options {
+    ///     fn target_filter(&self) -> Vec<String> { vec!["foobar".into()] }
+    ///     fn target_excludes(&self) -> Vec<String> { vec!["foobar::chatty".into()] }
+    ///     fn max_log_level(&self) -> log::LevelFilter { log::LevelFilter::Info }
+    /// }
+    ///
+    /// # fn main() {
+    /// let buffer = Buffer::default();
+    /// let logger = Logger::new(buffer.clone(), &Options);
+    /// for &target in &["foobar", "foobar::db", "foobar::chatty", "foobar::chatty::io", "hyper"] {
+    ///     logger.log(&Record::builder()
+    ///         .level(Level::Info)
+    ///         .target(target)
+    ///         .args(format_args!("from {}", target))
+    ///         .build());
+    /// }
+    ///
+    /// let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    /// assert_eq!(output, "from foobar\nfrom foobar::db\n");
+    /// # }
+    /// ```
+    fn target_excludes(&self) -> Vec<String> {
+        Vec::new()
+    }
+
     /// Controls minimum level of messages to be logged.
     ///
     /// Messages lower than this level will not be printed.
     fn max_log_level(&self) -> log::LevelFilter;
+
+    /// Format of emitted lines
+    ///
+    /// The systemd level prefix is never written in JSON mode since consumers
+    /// are expected to read the `level` field instead.
+    fn format(&self) -> LogFormat {
+        LogFormat::Text
+    }
+
+    /// Prepend an RFC3339 UTC timestamp to each text line
+    ///
+    /// The timestamp is written before the systemd level prefix, e.g.
+    /// `2024-01-02T15:04:05.123Z <5> message`. Note that systemd only
+    /// recognizes a level prefix at the start of a line, so this is mostly
+    /// useful when logging somewhere other than the journal.
+    fn include_timestamp(&self) -> bool {
+        false
+    }
+
+    /// Per-target overrides of `max_log_level`
+    ///
+    /// Each entry pairs a target prefix with the level to use for records
+    /// whose target starts with it, e.g. `("foobar::noisy", Warn)`. The longest
+    /// matching prefix wins, and targets without a match use `max_log_level`.
+    fn level_overrides(&self) -> Vec<(String, log::LevelFilter)> {
+        Vec::new()
+    }
+
+    /// Filtering directives in `RUST_LOG` syntax, e.g. `foobar=debug,hyper=warn`
+    ///
+    /// Typically read from the config file. Like `RUST_LOG`, these replace the
+    /// target filter and levels above entirely, while lines are still
+    /// formatted according to these options. When `RUST_LOG` is also set, the
+    /// environment variable wins. Defaults to `None`.
+    fn log_directives(&self) -> Option<String> {
+        None
+    }
+
+    /// Write log lines from a dedicated thread through a queue of this depth
+    ///
+    /// Log calls then only format the line and enqueue it rather than
+    /// contending for the output. The writer thread batches lines and writes
+    /// them at least every 100ms; flushing the logger waits for the queue to
+    /// drain. A full queue blocks log calls rather than dropping lines.
+    /// Defaults to `None`, which writes synchronously.
+    fn async_buffer(&self) -> Option<usize> {
+        None
+    }
+
+    /// Color the message of each text line according to its level
+    ///
+    /// With `Auto`, colors are only used when `init` finds that stdout is a
+    /// terminal and no `log_file` is set; a `Logger` created directly only
+    /// colors with `Always`. JSON lines are never colored.
+    ///
+    /// The `CLICOLOR_FORCE` and `NO_COLOR` environment variables take
+    /// precedence over this, in that order. Either counts as set when it's
+    /// non-empty, and `CLICOLOR_FORCE=0` doesn't count.
+    fn color(&self) -> ColorChoice {
+        ColorChoice::Auto
+    }
+
+    /// Also append every line to this file
+    ///
+    /// Lines are still written to stdout, or `writer`, as well. The file is
+    /// created if it doesn't exist, and failing to open it is fatal. It is
+    /// rotated as described by `log_max_bytes` and `log_keep`.
+    fn log_file(&self) -> Option<PathBuf> {
+        None
+    }
+
+    /// Write lines here instead of stdout
+    ///
+    /// The writer is line buffered, like stdout. Colors are only used with
+    /// `ColorChoice::Always` since it isn't known to be a terminal. Defaults
+    /// to `None`, which writes to stdout.
+    fn writer(&self) -> Option<Box<dyn io::Write + Send>> {
+        None
+    }
+
+    /// Rotate the `log_file` once it would grow beyond this many bytes
+    ///
+    /// The file is also rotated daily regardless of this setting. Defaults to
+    /// `None`, which only rotates by day.
+    fn log_max_bytes(&self) -> Option<u64> {
+        None
+    }
+
+    /// Number of rotated log files to keep as `<log_file>.1`, `.2`, and so on
+    fn log_keep(&self) -> usize {
+        7
+    }
+
+    /// Signal which makes `run` reopen the `log_file`
+    ///
+    /// This is for an external `logrotate` which moves the file away and then
+    /// sends the signal; lines written up to that point go to the moved file,
+    /// and later ones to a new file at the path. `run` listens for the signal
+    /// whenever there's a log file, whether or not it's in
+    /// `Application::signals`, and it isn't passed on to the application. That
+    /// takes precedence over USR1 raising the log level. Defaults to USR1.
+    fn reopen_signal(&self) -> Option<Signal> {
+        Some(Signal::USR1)
+    }
+
+    /// Append the source location of each record, e.g. `(src/foo.rs:42)`
+    ///
+    /// Nothing is appended for records without a file. JSON lines get
+    /// separate `file` and `line` fields instead.
+    fn include_location(&self) -> bool {
+        false
+    }
+
+    /// Prefix each text line with the name of the logging thread
+    ///
+    /// Unnamed threads are identified by their `ThreadId` instead. Text lines
+    /// are laid out as `<timestamp> <systemd level> [thread] message
+    /// (location)`, with each part present only when enabled. JSON lines get a
+    /// `thread` field instead.
+    fn include_thread(&self) -> bool {
+        false
+    }
+
+    /// Send records to the systemd journal using its native protocol
+    ///
+    /// Records then carry `PRIORITY`, `SYSLOG_IDENTIFIER`, `CODE_FILE`, and
+    /// `CODE_LINE` fields which can be queried with `journalctl`, and the
+    /// formatting options above don't apply. When the journal socket isn't
+    /// available, lines are written to the usual output instead.
+    fn journald(&self) -> bool {
+        false
+    }
+
+    /// Send records to a syslog daemon as RFC 5424 messages
+    ///
+    /// Messages use the daemon facility, a severity matching the level, and
+    /// the program name as the app name. The formatting options above don't
+    /// apply. Messages which can't be delivered are retried with later ones
+    /// and eventually dropped; logging never fails because of the target.
+    /// `journald` takes precedence when both are enabled.
+    fn syslog(&self) -> Option<SyslogTarget> {
+        None
+    }
+
+    /// Collapse identical messages repeated within this window
+    ///
+    /// Repeats of a message with the same level and target are suppressed
+    /// until the window after its first appearance closes. Once it has,
+    /// `last message repeated N times` is emitted with the next record logged
+    /// or when the logger is flushed, whichever comes first. Only the most
+    /// recent distinct messages are tracked. Defaults to `None`, which logs
+    /// every message.
+    ///
+    /// ```rust
+    /// # extern crate log;
+    /// # extern crate system_service;
+    /// # use std::io::{self, Write};
+    /// # use std::sync::{Arc, Mutex};
+    /// # use std::time::{Duration, Instant, SystemTime};
+    /// # use log::{Level, Log, Record};
+    /// # use system_service::{Clock, LogOptions, Logger};
+    /// # #[derive(Clone, Default)]
+    /// # struct Buffer(Arc<Mutex<Vec<u8>>>);
+    /// # impl Write for Buffer {
+    /// #     fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.0.lock().unwrap().write(buf) }
+    /// #     fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    /// # }
+    /// # struct Stepped(Mutex<Instant>);
+    /// # impl Clock for Stepped {
+    /// #     fn now_instant(&self) -> Instant { *self.0.lock().unwrap() }
+    /// #     fn now_system(&self) -> SystemTime { SystemTime::now() }
+    /// # }
+    /// struct Options;
+    ///
+    /// impl LogOptions for Options {
+    ///     fn target_filter(&self) -> Vec<String> { vec!["app".into()] }
+    ///     fn max_log_level(&self) -> log::LevelFilter { log::LevelFilter::Info }
+    ///     fn dedup_window(&self) -> Option<Duration> { Some(Duration::from_secs(10)) }
+    /// }
+    ///
+    /// # fn main() {
+    /// let buffer = Buffer::default();
+    /// let clock = Arc::new(Stepped(Mutex::new(Instant::now())));
+    /// let logger = Logger::new(buffer.clone(), &Options).with_clock(clock.clone());
+    /// for _ in 0..3 {
+    ///     logger.log(&Record::builder()
+    ///         .level(Level::Warn)
+    ///         .target("app")
+    ///         .args(format_args!("disk full"))
+    ///         .build());
+    /// }
+    ///
+    /// *clock.0.lock().unwrap() += Duration::from_secs(10);
+    /// logger.flush();
+    ///
+    /// let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    /// assert_eq!(output, "disk full\nlast message repeated 2 times\n");
+    /// # }
+    /// ```
+    fn dedup_window(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Format lines with a function of your own instead of `format`
+    ///
+    /// The function returns the whole line without its trailing newline.
+    /// Timestamps, level prefixes, colors, and the other formatting options
+    /// don't apply to it. The journal and syslog outputs don't use it either.
+    /// Defaults to `None`, which uses the built-in formats.
+    fn formatter(&self) -> Option<LogFormatter> {
+        None
+    }
 }
 
-impl<'a> LogOptions for &'a LogOptions {
+/// A function formatting a record as a complete line, without the newline
+pub type LogFormatter = Box<dyn Fn(&log::Record) -> String + Send + Sync>;
+
+impl LogOptions for &dyn LogOptions {
     fn include_systemd_level(&self) -> bool {
         (*self).include_systemd_level()
     }
 
+    fn info_as_notice(&self) -> bool {
+        (*self).info_as_notice()
+    }
+
     fn target_filter(&self) -> Vec<String> {
         (*self).target_filter()
     }
 
+    fn target_excludes(&self) -> Vec<String> {
+        (*self).target_excludes()
+    }
+
     fn max_log_level(&self) -> log::LevelFilter {
         (*self).max_log_level()
     }
+
+    fn format(&self) -> LogFormat {
+        (*self).format()
+    }
+
+    fn include_timestamp(&self) -> bool {
+        (*self).include_timestamp()
+    }
+
+    fn level_overrides(&self) -> Vec<(String, log::LevelFilter)> {
+        (*self).level_overrides()
+    }
+
+    fn log_directives(&self) -> Option<String> {
+        (*self).log_directives()
+    }
+
+    fn async_buffer(&self) -> Option<usize> {
+        (*self).async_buffer()
+    }
+
+    fn color(&self) -> ColorChoice {
+        (*self).color()
+    }
+
+    fn log_file(&self) -> Option<PathBuf> {
+        (*self).log_file()
+    }
+
+    fn writer(&self) -> Option<Box<dyn io::Write + Send>> {
+        (*self).writer()
+    }
+
+    fn log_max_bytes(&self) -> Option<u64> {
+        (*self).log_max_bytes()
+    }
+
+    fn log_keep(&self) -> usize {
+        (*self).log_keep()
+    }
+
+    fn reopen_signal(&self) -> Option<Signal> {
+        (*self).reopen_signal()
+    }
+
+    fn include_location(&self) -> bool {
+        (*self).include_location()
+    }
+
+    fn include_thread(&self) -> bool {
+        (*self).include_thread()
+    }
+
+    fn journald(&self) -> bool {
+        (*self).journald()
+    }
+
+    fn syslog(&self) -> Option<SyslogTarget> {
+        (*self).syslog()
+    }
+
+    fn dedup_window(&self) -> Option<Duration> {
+        (*self).dedup_window()
+    }
+
+    fn formatter(&self) -> Option<LogFormatter> {
+        (*self).formatter()
+    }
 }
 
+/// The built-in logger installed by `run`
+///
+/// # Example
+///
+/// Each level is written with the expected systemd prefix.
+///
+/// ```rust
+/// # extern crate log;
+/// # extern crate system_service;
+/// # use std::io::{self, Write};
+/// # use std::sync::{Arc, Mutex};
+/// # use log::{Level, Log, Record};
+/// # use system_service::{LogOptions, Logger};
+/// #[derive(Clone, Default)]
+/// struct Buffer(Arc<Mutex<Vec<u8>>>);
+///
+/// impl Write for Buffer {
+///     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+///         self.0.lock().unwrap().write(buf)
+///     }
+///
+///     fn flush(&mut self) -> io::Result<()> {
+///         Ok(())
+///     }
+/// }
+///
+/// struct Options {
+///     info_as_notice: bool,
+/// }
+///
+/// impl LogOptions for Options {
+///     fn include_systemd_level(&self) -> bool { true }
+///     fn info_as_notice(&self) -> bool { self.info_as_notice }
+///     fn target_filter(&self) -> Vec<String> { vec!["app".into()] }
+///     fn max_log_level(&self) -> log::LevelFilter { log::LevelFilter::Trace }
+/// }
+///
+/// # fn main() {
+/// let levels = [Level::Error, Level::Warn, Level::Info, Level::Debug, Level::Trace];
+/// let cases = [
+///     (false, ["<3>", "<4>", "<6>", "<7>", "<7>"]),
+///     (true, ["<3>", "<4>", "<5>", "<7>", "<7>"]),
+/// ];
+///
+/// for &(info_as_notice, expected) in &cases {
+///     let buffer = Buffer::default();
+///     let logger = Logger::new(buffer.clone(), &Options { info_as_notice });
+///     for &level in &levels {
+///         logger.log(&Record::builder()
+///             .level(level)
+///             .target("app")
+///             .args(format_args!("message"))
+///             .build());
+///     }
+///
+///     let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+///     let prefixes: Vec<&str> = output.lines().map(|l| &l[..3]).collect();
+///     assert_eq!(prefixes, expected);
+/// }
+/// # }
+/// ```
 pub struct Logger<T> {
-    level: log::LevelFilter,
-    output: sync::Mutex<T>,
+    level: Arc<LevelControl>,
+    output: Output<T>,
     target_filter: Vec<String>,
+    target_excludes: Vec<String>,
     include_systemd_level: bool,
+    info_as_notice: bool,
+    format: LogFormat,
+    include_timestamp: bool,
+    level_overrides: Vec<(String, log::LevelFilter)>,
+    env_filter: Option<filter::Filter>,
+    color: bool,
+    include_location: bool,
+    include_thread: bool,
+    dedup: Option<dedup::Dedup>,
+    clock: Arc<dyn Clock>,
+    dropped: Arc<Dropped>,
+    formatter: Option<LogFormatter>,
 }
 
-impl<T: Send + io::Write> Logger<T> {
+/// Where a `Logger` sends formatted lines
+enum Output<T> {
+    /// Written directly while holding a lock
+    Locked(sync::Mutex<T>),
+
+    /// Queued for a writer thread which owns the output
+    Queued(queue::Queue),
+
+    /// Sent to the systemd journal in place of the output
+    Journal(journald::Journal),
+
+    /// Sent to a syslog daemon in place of the output
+    Syslog(syslog::Syslog),
+}
+
+impl<T: Send + io::Write + 'static> Logger<T> {
+    /// Create a logger writing lines to `output` as configured by `options`
+    ///
+    /// This also sets the global maximum log level. Panics if the writer
+    /// thread for `LogOptions::async_buffer` can't be started.
     pub fn new<O: LogOptions>(
         output: T,
         options: &O,
     ) -> Logger<io::LineWriter<T>> {
+        Logger::try_new(output, options).expect("failed to spawn log writer thread")
+    }
+
+    /// Same as `new`, but returning an error if a thread can't be started
+    pub(crate) fn try_new<O: LogOptions>(
+        output: T,
+        options: &O,
+    ) -> io::Result<Logger<io::LineWriter<T>>> {
+        Logger::build(io::LineWriter::new(output), options, true)
+    }
+
+    /// Create a logger writing to `output` without buffering it
+    ///
+    /// Each record is passed to `output` whole, in one call to `write_all`,
+    /// which suits message-oriented writers such as datagram sockets where
+    /// line buffering would split or merge records. With
+    /// `LogOptions::async_buffer`, records are also written one at a time
+    /// rather than batched. `output` is flushed only when the logger is. Panics
+    /// as `new` does.
+    pub fn new_unbuffered<O: LogOptions>(output: T, options: &O) -> Logger<T> {
+        Logger::build(output, options, false).expect("failed to spawn log writer thread")
+    }
+
+    fn build<O: LogOptions>(output: T, options: &O, batch: bool) -> io::Result<Logger<T>> {
+        let journal = if options.journald() {
+            journald::Journal::connect().ok()
+        } else {
+            None
+        };
+        let syslog = options.syslog().and_then(|target| syslog::Syslog::new(target).ok());
+        let dropped = Arc::new(Dropped::new());
+
+        let output = match (journal, syslog, options.async_buffer()) {
+            (Some(journal), _, _) => Output::Journal(journal),
+            (None, Some(syslog), _) => Output::Syslog(syslog),
+            (None, None, Some(depth)) => {
+                Output::Queued(queue::Queue::spawn(output, depth, batch, dropped.clone())?)
+            },
+            (None, None, None) => Output::Locked(sync::Mutex::new(output)),
+        };
+
         let level = options.max_log_level();
-        log::set_max_level(level);
-        Logger {
-            level: level,
-            output: sync::Mutex::new(io::LineWriter::new(output)),
+
+        // Longest prefixes first so the first match is the most specific.
+        let mut level_overrides = options.level_overrides();
+        level_overrides.sort_by_key(|o| ::std::cmp::Reverse(o.0.len()));
+
+        let floor = level_overrides.iter().map(|o| o.1).max().unwrap_or(log::LevelFilter::Off);
+        let level = Arc::new(LevelControl::new(level, floor));
+
+        Ok(Logger {
+            level,
+            output,
             target_filter: options.target_filter(),
+            target_excludes: options.target_excludes(),
             include_systemd_level: options.include_systemd_level(),
+            info_as_notice: options.info_as_notice(),
+            format: options.format(),
+            include_timestamp: options.include_timestamp(),
+            level_overrides,
+            env_filter: None,
+            color: use_color(options.color(), false),
+            include_location: options.include_location(),
+            include_thread: options.include_thread(),
+            dedup: options.dedup_window().map(dedup::Dedup::new),
+            clock: Arc::new(SystemClock),
+            dropped,
+            formatter: options.formatter(),
+        })
+    }
+}
+
+impl<T: Send + io::Write> Logger<T> {
+    /// Number of lines lost because they couldn't be written
+    ///
+    /// This includes lines dropped by a syslog target which stayed
+    /// unreachable. See also `dropped_log_lines`, for the installed logger.
+    pub fn dropped_count(&self) -> u64 {
+        self.dropped.count()
+    }
+
+    /// Whether the output failed permanently, such as a pipe whose reader exited
+    ///
+    /// Lines are then dropped without being written, after one notice on
+    /// stderr, until the log file is reopened.
+    pub fn output_broken(&self) -> bool {
+        self.dropped.output_broken()
+    }
+
+    /// Read timestamps and dedup windows from `clock` instead of the system
+    /// clocks
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Logger<T> {
+        self.clock = clock;
+        self
+    }
+
+    /// Filter messages with `RUST_LOG`-style directives
+    ///
+    /// This replaces filtering by the target filter and levels from
+    /// `LogOptions` entirely.
+    pub(crate) fn set_env_filter(&mut self, filter: filter::Filter) {
+        log::set_max_level(filter.filter());
+        self.env_filter = Some(filter);
+    }
+
+    /// Effective maximum level for messages from `target`
+    fn level_for(&self, target: &str) -> log::LevelFilter {
+        self.level_overrides
+            .iter()
+            .find(|o| target.starts_with(&o.0))
+            .map(|o| o.1)
+            .unwrap_or_else(|| self.level.get())
+    }
+
+    /// Map a log level to a syslog severity
+    ///
+    /// Error, warning, info, and debug levels are as expected, though "Info"
+    /// can optionally be mapped to the "Notice" system level. Both Trace and
+    /// Debug are recorded at the syslog Debug level since there's no trace
+    /// level.
+    fn severity(&self, level: log::Level) -> usize {
+        use ::log::Level::*;
+        match level {
+            Error => 3,
+            Warn => 4,
+            Info if self.info_as_notice => 5,
+            Info => 6,
+            Debug => 7,
+            Trace => 7,
         }
     }
 
     /// Map a log level to a systemd level prefix
     ///
     /// Systemd can consume a leading numeric prefix in brackets to choose which
-    /// system log level to record the message at. The log crate's "Info" level
-    /// is mapped to "Notice" system level since they seem semantically
-    /// equivalent. Warning, error, and debug levels are as expected. Both Trace
-    /// and Debug are recorded at the syslog Debug level since there's no trace
-    /// level.
+    /// system log level to record the message at.
     fn systemd_level(&self, record: &log::Record) -> &'static str {
-        use ::log::Level::*;
-        if self.include_systemd_level {
-            match record.level() {
-                Error => "<3> ",
-                Warn => "<4> ",
-                Info => "<5> ",
-                Debug => "<7> ",
-                Trace => "<7> ",
-            }
+        const PREFIXES: [&str; 8] = [
+            "<0> ", "<1> ", "<2> ", "<3> ", "<4> ", "<5> ", "<6> ", "<7> ",
+        ];
+
+        if self.include_systemd_level && self.format == LogFormat::Text {
+            PREFIXES[self.severity(record.level())]
         } else {
             ""
         }
     }
+
+    /// Emit the count of a message's repeats suppressed by `dedup_window`
+    fn report_repeated(&self, level: log::Level, target: &str, repeated: u64) {
+        self.emit(&log::Record::builder()
+            .args(format_args!("last message repeated {} times", repeated))
+            .level(level)
+            .target(target)
+            .build());
+    }
+
+    /// Send a record which passed filtering to the output
+    fn emit(&self, record: &log::Record) {
+        match self.output {
+            Output::Locked(ref output) => {
+                if self.dropped.output_broken() {
+                    return self.dropped.add(1, self.clock.now_instant());
+                }
+
+                // Formatting first means the output sees each line in a
+                // single write.
+                let mut line = Vec::new();
+                let _ = self.write_record(&mut line, record);
+
+                if let Ok(ref mut writer) = output.lock() {
+                    // Nothing we can do with an error here other than panic
+                    // the program, and that doesn't sound great either, so
+                    // it's only counted.
+                    if let Err(err) = writer.write_all(&line) {
+                        self.dropped.failed(1, &err, self.clock.now_instant());
+                    }
+                }
+            },
+            Output::Queued(ref queue) => {
+                let mut line = Vec::new();
+                let _ = self.write_record(&mut line, record);
+                queue.send(line);
+            },
+            Output::Journal(ref journal) => {
+                if journal.send(record, self.severity(record.level())).is_err() {
+                    self.dropped.add(1, self.clock.now_instant());
+                }
+            },
+            Output::Syslog(ref syslog) => {
+                let now = self.clock.now_system();
+                let dropped = syslog.send(record, self.severity(record.level()), now);
+                self.dropped.add(dropped, self.clock.now_instant());
+            },
+        }
+    }
+
+    /// Format a record as a complete line
+    fn write_record<W: io::Write>(&self, writer: &mut W, record: &log::Record) -> io::Result<()> {
+        if let Some(ref formatter) = self.formatter {
+            return writeln!(writer, "{}", formatter(record));
+        }
+
+        match self.format {
+            LogFormat::Text => {
+                let timestamp = Timestamp(if self.include_timestamp {
+                    Some(self.clock.now_system())
+                } else {
+                    None
+                });
+                let prefix = self.systemd_level(record);
+                let thread = ThreadName(self.include_thread);
+                let location = Location(record, self.include_location);
+                let fields = KeyValues(record);
+                if self.color {
+                    let color = ansi_color(record.level());
+                    writeln!(
+                        writer,
+                        "{}{}{}\x1b[{}m{}\x1b[0m{}{}",
+                        timestamp, prefix, thread, color, record.args(), fields, location,
+                    )
+                } else {
+                    writeln!(
+                        writer,
+                        "{}{}{}{}{}{}",
+                        timestamp, prefix, thread, record.args(), fields, location,
+                    )
+                }
+            },
+            LogFormat::Json => {
+                writeln!(writer, "{}", JsonRecord {
+                    record,
+                    location: self.include_location,
+                    thread: self.include_thread,
+                    now: self.clock.now_system(),
+                })
+            },
+        }
+    }
 }
 
 impl<T: Send + io::Write> log::Log for Logger<T> {
     fn enabled(&self, metadata: &log::Metadata) -> bool {
-        metadata.level() <= self.level
+        match self.env_filter {
+            Some(ref filter) => filter.enabled(metadata),
+            None => metadata.level() <= self.level_for(metadata.target()),
+        }
     }
 
     fn log(&self, record: &log::Record) {
-        if self.enabled(record.metadata()) &&
-            self.target_filter.iter().any(|t| record.target().starts_with(t))
-        {
-            let prefix = self.systemd_level(record);
+        let emit = match self.env_filter {
+            Some(ref filter) => filter.matches(record),
+            None => {
+                self.enabled(record.metadata()) &&
+                    self.target_filter.iter().any(|t| record.target().starts_with(t)) &&
+                    !self.target_excludes.iter().any(|t| record.target().starts_with(t))
+            },
+        };
+
+        if !emit {
+            return;
+        }
+
+        if let Some(ref dedup) = self.dedup {
+            let now = self.clock.now_instant();
+            let fresh = dedup.check(record, now, |level, target, repeated| {
+                self.report_repeated(level, target, repeated);
+            });
+
+            if !fresh {
+                return;
+            }
+        }
+
+        self.emit(record);
 
-            if let Ok(ref mut writer) = self.output.lock() {
-                // Nothing we can do with an error here other than panic the
-                // program, and that doesn't sound great either.
-                let _ = writeln!(writer, "{}{}", prefix, record.args());
+        // Also hand the record to a tracing subscriber, if there is one, so
+        // it's recorded alongside events from the current span.
+        #[cfg(feature = "tracing")]
+        {
+            if tracing::dispatcher::has_been_set() {
+                let _ = tracing_log::format_trace(record);
             }
         }
     }
 
     fn flush(&self) {
-        if let Ok(ref mut output) = self.output.lock() {
-            let _ = output.flush();
+        if let Some(ref dedup) = self.dedup {
+            dedup.expire(self.clock.now_instant(), |level, target, repeated| {
+                self.report_repeated(level, target, repeated);
+            });
+        }
+
+        match self.output {
+            Output::Locked(ref output) => {
+                if self.dropped.output_broken() {
+                    return;
+                }
+                if let Ok(ref mut output) = output.lock() {
+                    let _ = output.flush();
+                }
+            },
+            Output::Queued(ref queue) => queue.flush(),
+            Output::Journal(_) | Output::Syslog(_) => (),
         }
     }
 }
 
-pub fn init<O: LogOptions>(options: &LogOptions) -> Result<(), log::SetLoggerError> {
-    // Use env_logger if RUST_LOG environment variable is defined. Otherwise,
-    // use the stdout program-only logger with optional systemd prefixing.
-    if ::std::env::var("RUST_LOG").is_ok() {
-        ::env_logger::try_init()
-    } else {
-        log::set_boxed_logger(Box::new(Logger::new(io::stdout(), &options)))
+/// Target for messages logged by this crate on the application's behalf
+///
+/// This is the first target of `target_filter` so the messages aren't
+/// filtered out along with other crates' messages.
+pub(crate) fn app_target<O: LogOptions>(options: &O) -> String {
+    options.target_filter()
+        .into_iter()
+        .next()
+        .unwrap_or_else(|| module_path!().to_owned())
+}
+
+/// Replace the panic hook with one which logs panics at the error level
+///
+/// Panics are logged with the `app_target` of `options`.
+pub(crate) fn log_panics<O: LogOptions>(options: &O) {
+    let target = app_target(options);
+
+    panic::set_hook(Box::new(move |info| {
+        let payload = info.payload();
+        let message = match payload.downcast_ref::<&str>() {
+            Some(message) => *message,
+            None => match payload.downcast_ref::<String>() {
+                Some(message) => &message[..],
+                None => "Box<dyn Any>",
+            },
+        };
+
+        let thread = thread::current();
+        let name = thread.name().unwrap_or("<unnamed>");
+        match info.location() {
+            Some(location) => {
+                error!(target: &target, "thread '{}' panicked at {}:{}: {}",
+                       name, location.file(), location.line(), message);
+            },
+            None => error!(target: &target, "thread '{}' panicked: {}", name, message),
+        }
+
+        // The process may be about to exit, so don't leave this queued.
+        log::logger().flush();
+    }));
+}
+
+/// Name identifying this program to the journal and syslog
+pub(crate) fn identifier() -> String {
+    env::args_os()
+        .next()
+        .as_ref()
+        .and_then(|arg| Path::new(arg).file_name())
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Writes everything to both the usual output and a file
+struct TeeWriter {
+    output: Box<dyn io::Write + Send>,
+
+    /// Shared with `Installed` so it can be reopened
+    file: Arc<sync::Mutex<RotatingFileWriter>>,
+
+    /// Whether `output` failed permanently, leaving only the file
+    output_broken: bool,
+}
+
+impl io::Write for TeeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Try both sinks before reporting an error so one failing doesn't
+        // starve the other.
+        let output = if self.output_broken { Ok(()) } else { self.output.write_all(buf) };
+        let file = self.file().and_then(|mut file| file.write_all(buf));
+
+        // A broken output would otherwise take the file down with it.
+        match output {
+            Err(ref err) if is_permanent(err) && file.is_ok() => {
+                self.output_broken = true;
+                let _ = writeln!(
+                    io::stderr(),
+                    "Log output failed ({}); writing only to the log file",
+                    err,
+                );
+            },
+            _ => (),
+        }
+        if self.output_broken { file } else { output.and(file) }.map(|_| buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let output = if self.output_broken { Ok(()) } else { self.output.flush() };
+        let file = self.file().and_then(|mut file| file.flush());
+        output.and(file)
+    }
+}
+
+impl TeeWriter {
+    fn file(&self) -> io::Result<sync::MutexGuard<'_, RotatingFileWriter>> {
+        self.file.lock().map_err(|_| io::Error::other("log file lock poisoned"))
+    }
+}
+
+/// Whether to color text lines, given the configured choice and whether the
+/// output is a terminal
+///
+/// `CLICOLOR_FORCE` wins over `NO_COLOR`, which wins over `choice`.
+fn use_color(choice: ColorChoice, terminal: bool) -> bool {
+    let force = env::var_os("CLICOLOR_FORCE").map(|v| !v.is_empty() && v != "0");
+    if force.unwrap_or(false) {
+        return true;
+    }
+
+    if env::var_os("NO_COLOR").map(|v| !v.is_empty()).unwrap_or(false) {
+        return false;
+    }
+
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => terminal,
+    }
+}
+
+/// ANSI SGR code for the color of messages at `level`
+fn ansi_color(level: log::Level) -> &'static str {
+    use ::log::Level::*;
+    match level {
+        Error => "31",
+        Warn => "33",
+        Info => "32",
+        Debug => "34",
+        Trace => "90",
+    }
+}
+
+/// Formats a time as an RFC3339 prefix when present
+struct Timestamp(Option<SystemTime>);
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(now) => write!(f, "{} ", humantime::format_rfc3339_millis(now)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Formats the source location of a record as a suffix when enabled
+struct Location<'a, 'b: 'a>(&'a log::Record<'b>, bool);
+
+impl<'a, 'b> fmt::Display for Location<'a, 'b> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.1 {
+            return Ok(());
+        }
+
+        match (self.0.file(), self.0.line()) {
+            (Some(file), Some(line)) => write!(f, " ({}:{})", file, line),
+            (Some(file), None) => write!(f, " ({})", file),
+            (None, _) => Ok(()),
+        }
+    }
+}
+
+/// Formats the current thread's name as a bracketed prefix when enabled
+struct ThreadName(bool);
+
+impl fmt::Display for ThreadName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.0 {
+            return Ok(());
+        }
+
+        let thread = thread::current();
+        match thread.name() {
+            Some(name) => write!(f, "[{}] ", name),
+            None => write!(f, "[{:?}] ", thread.id()),
+        }
+    }
+}
+
+/// Formats a record as a single-line JSON object, with optional fields
+struct JsonRecord<'a, 'b: 'a> {
+    record: &'a log::Record<'b>,
+    location: bool,
+    thread: bool,
+    now: SystemTime,
+}
+
+impl<'a, 'b> fmt::Display for JsonRecord<'a, 'b> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let record = self.record;
+        write!(
+            f,
+            "{{\"level\":\"{}\",\"target\":{},\"message\":{},\"timestamp\":\"{}\"",
+            record.level(),
+            JsonStr(record.target()),
+            JsonStr(&record.args().to_string()),
+            humantime::format_rfc3339_millis(self.now),
+        )?;
+
+        if self.thread {
+            let thread = thread::current();
+            match thread.name() {
+                Some(name) => write!(f, ",\"thread\":{}", JsonStr(name))?,
+                None => write!(f, ",\"thread\":\"{:?}\"", thread.id())?,
+            }
+        }
+
+        if self.location {
+            if let Some(file) = record.file() {
+                write!(f, ",\"file\":{}", JsonStr(file))?;
+            }
+            if let Some(line) = record.line() {
+                write!(f, ",\"line\":{}", line)?;
+            }
+        }
+
+        for_each_pair(record, |key, value| {
+            write!(f, ",{}:", JsonStr(key.as_str()))?;
+            if let Some(b) = value.to_bool() {
+                write!(f, "{}", b)
+            } else if let Some(n) = value.to_i64() {
+                write!(f, "{}", n)
+            } else if let Some(n) = value.to_u64() {
+                write!(f, "{}", n)
+            } else {
+                match value.to_f64() {
+                    Some(n) if n.is_finite() => write!(f, "{}", n),
+                    _ => write!(f, "{}", JsonStr(&value.to_string())),
+                }
+            }
+        })?;
+
+        f.write_str("}")
+    }
+}
+
+/// Formats the key-value pairs of a record as ` key=value` suffixes
+///
+/// Values which are empty or contain whitespace, quotes, or `=` are quoted.
+struct KeyValues<'a, 'b: 'a>(&'a log::Record<'b>);
+
+impl<'a, 'b> fmt::Display for KeyValues<'a, 'b> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for_each_pair(self.0, |key, value| {
+            let value = value.to_string();
+            let quote = value.is_empty() || value.contains(|c: char| {
+                c.is_whitespace() || c == '"' || c == '='
+            });
+            if quote {
+                write!(f, " {}={:?}", key, value)
+            } else {
+                write!(f, " {}={}", key, value)
+            }
+        })
+    }
+}
+
+/// Call `f` with each key-value pair attached to `record`
+fn for_each_pair<F>(record: &log::Record, f: F) -> fmt::Result
+    where F: FnMut(log::kv::Key, log::kv::Value) -> fmt::Result
+{
+    struct Visitor<F>(F);
+
+    impl<'kvs, F> log::kv::VisitSource<'kvs> for Visitor<F>
+        where F: FnMut(log::kv::Key, log::kv::Value) -> fmt::Result
+    {
+        fn visit_pair(
+            &mut self,
+            key: log::kv::Key<'kvs>,
+            value: log::kv::Value<'kvs>,
+        ) -> Result<(), log::kv::Error> {
+            (self.0)(key, value).map_err(log::kv::Error::from)
+        }
+    }
+
+    record.key_values().visit(&mut Visitor(f)).map_err(|_| fmt::Error)
+}
+
+/// Formats a string as a quoted and escaped JSON string
+pub(crate) struct JsonStr<'a>(pub(crate) &'a str);
+
+impl<'a> fmt::Display for JsonStr<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use std::fmt::Write;
+
+        f.write_char('"')?;
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\r' => f.write_str("\\r")?,
+                '\t' => f.write_str("\\t")?,
+                c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                c => f.write_char(c)?,
+            }
+        }
+        f.write_char('"')
+    }
+}
+
+/// Hold log records in memory until `init` installs the real logger
+///
+/// The records, up to a limit, are then replayed through it, so messages
+/// logged while loading options and config aren't lost.
+pub(crate) fn bootstrap() {
+    let _ = bootstrap::install();
+}
+
+/// Install the global logger, writing to stderr rather than stdout when
+/// `stderr` is true and there's no `LogOptions::writer`
+pub fn init<O: LogOptions>(options: &O, stderr: bool) -> Result<(), InitError> {
+    let (output, terminal) = match options.writer() {
+        Some(writer) => (writer, false),
+        None if stderr => {
+            let stderr = io::stderr();
+            let terminal = stderr.is_terminal();
+            (Box::new(stderr) as Box<dyn io::Write + Send>, terminal)
+        },
+        None => {
+            let stdout = io::stdout();
+            let terminal = stdout.is_terminal();
+            (Box::new(stdout) as Box<dyn io::Write + Send>, terminal)
+        },
+    };
+
+    match options.log_file() {
+        Some(path) => {
+            let file = RotatingFileWriter::open(
+                path.clone(),
+                options.log_max_bytes(),
+                options.log_keep(),
+            ).unwrap_or_else(|err| {
+                die!("Failed to open log file {}: {}", path.display(), err);
+            });
+
+            // Automatic colors would end up in the file too, so they're only
+            // used here when explicitly asked for.
+            let file = Arc::new(sync::Mutex::new(file));
+            let output = TeeWriter { output, file: file.clone(), output_broken: false };
+            install(Logger::try_new(output, options)?, false, Some(file), options)
+        },
+        None => install(Logger::try_new(output, options)?, terminal, None, options),
+    }
+}
+
+/// Error installing the global logger with `init`
+#[derive(Debug)]
+pub enum InitError {
+    /// Another global logger was installed first
+    SetLogger(log::SetLoggerError),
+
+    /// The writer thread for `LogOptions::async_buffer` couldn't be started
+    Spawn(io::Error),
+}
+
+impl From<log::SetLoggerError> for InitError {
+    fn from(err: log::SetLoggerError) -> InitError {
+        InitError::SetLogger(err)
     }
 }
 
+impl From<io::Error> for InitError {
+    fn from(err: io::Error) -> InitError {
+        InitError::Spawn(err)
+    }
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InitError::SetLogger(ref err) => err.fmt(f),
+            InitError::Spawn(ref err) => write!(f, "failed to start log writer thread: {}", err),
+        }
+    }
+}
+
+/// Finish configuring `logger` and make it the global logger
+fn install<T, O>(
+    mut logger: Logger<T>,
+    terminal: bool,
+    log_file: Option<Arc<sync::Mutex<RotatingFileWriter>>>,
+    options: &O,
+) -> Result<(), InitError>
+    where T: Send + io::Write + 'static,
+          O: LogOptions
+{
+    logger.color = use_color(options.color(), terminal);
+
+    // The RUST_LOG environment variable or directives from the options take
+    // over filtering when defined, but messages are still formatted according
+    // to the options.
+    if let Some(spec) = env::var("RUST_LOG").ok().or_else(|| options.log_directives()) {
+        logger.set_env_filter(filter::Builder::new().parse(&spec).build());
+    }
+
+    let installed = Installed {
+        dropped: logger.dropped.clone(),
+        level: match logger.env_filter {
+            Some(_) => None,
+            None => Some(logger.level.clone()),
+        },
+        target: app_target(options),
+        reopen_signal: log_file.as_ref().and_then(|_| options.reopen_signal()),
+        log_file: log_file.map(|file| (file, options.log_file().unwrap_or_default())),
+    };
+    // Only a logger which actually took over is recorded as installed.
+    bootstrap::set(Box::new(logger), &installed.target)?;
+    let _ = INSTALLED.set(installed);
+    Ok(())
+}
+
+/// Shared state of the logger installed by `init`
+struct Installed {
+    dropped: Arc<Dropped>,
+
+    /// `None` when `RUST_LOG` or `LogOptions::log_directives` took over
+    /// filtering
+    level: Option<Arc<LevelControl>>,
+
+    target: String,
+
+    /// The `log_file` writer and its path
+    log_file: Option<(Arc<sync::Mutex<RotatingFileWriter>>, PathBuf)>,
+
+    /// `LogOptions::reopen_signal`, when there's a log file
+    reopen_signal: Option<Signal>,
+}
+
+static INSTALLED: sync::OnceLock<Installed> = sync::OnceLock::new();
+
+/// Base level of the logger installed by `init`
+///
+/// `None` when no logger was installed or `RUST_LOG` or
+/// `LogOptions::log_directives` controls filtering.
+pub(crate) fn log_level() -> Option<log::LevelFilter> {
+    INSTALLED.get().and_then(|i| i.level.as_ref()).map(|level| level.get())
+}
+
+/// Target for messages from this crate which operators should see
+///
+/// This is the application's target when `init` installed the logger, so
+/// that `LogOptions::target_filter` lets the messages through.
+pub(crate) fn visible_target() -> &'static str {
+    INSTALLED.get().map(|installed| &installed.target[..]).unwrap_or("system_service")
+}
+
+/// Change the base level of the logger installed by `init`
+///
+/// Returns false when there's no level to change; see `log_level`.
+pub(crate) fn set_log_level(level: log::LevelFilter) -> bool {
+    let installed = match INSTALLED.get() {
+        Some(installed) => installed,
+        None => return false,
+    };
+
+    match installed.level {
+        Some(ref control) => {
+            control.set(level);
+            info!(target: &installed.target, "Log level set to {}", level);
+            true
+        },
+        None => false,
+    }
+}
+
+/// Make the installed logger one step more verbose, up to `Trace`
+pub(crate) fn raise_log_level() -> bool {
+    match log_level() {
+        Some(level) => set_log_level(LevelControl::from_usize(level as usize + 1)),
+        None => false,
+    }
+}
+
+/// Put the installed logger back to `LogOptions::max_log_level`
+pub(crate) fn reset_log_level() -> bool {
+    match INSTALLED.get().and_then(|i| i.level.as_ref()) {
+        Some(control) => set_log_level(control.initial),
+        None => false,
+    }
+}
+
+/// Signal which reopens the log file of the logger installed by `init`
+///
+/// `None` when there's no log file.
+pub(crate) fn reopen_signal() -> Option<Signal> {
+    INSTALLED.get().and_then(|installed| installed.reopen_signal)
+}
+
+/// Reopen the log file of the logger installed by `init`
+///
+/// Writes wait on the file's lock meanwhile, so no lines are lost.
+pub(crate) fn reopen_log_file() {
+    let installed = match INSTALLED.get() {
+        Some(installed) => installed,
+        None => return,
+    };
+    installed.dropped.repaired();
+    let (file, path) = match installed.log_file {
+        Some((ref file, ref path)) => (file, path),
+        None => return,
+    };
+
+    // The lock must be released before logging about it.
+    let result = match file.lock() {
+        Ok(mut file) => file.reopen(),
+        Err(_) => return,
+    };
+
+    match result {
+        Ok(true) => info!(target: &installed.target, "Reopened log file {}", path.display()),
+        Ok(false) => (),
+        Err(err) => warn!(
+            target: &installed.target,
+            "Failed to reopen log file {}: {}",
+            path.display(),
+            err,
+        ),
+    }
+}
+
+/// The base level of a logger, which can change while it's in use
+///
+/// Level overrides stay as they are, so the global maximum level is never
+/// set below the most verbose of them.
+struct LevelControl {
+    current: AtomicUsize,
+    initial: log::LevelFilter,
+    floor: log::LevelFilter,
+}
+
+impl LevelControl {
+    /// Start at `level`, also setting the global maximum level
+    fn new(level: log::LevelFilter, floor: log::LevelFilter) -> LevelControl {
+        let control = LevelControl {
+            current: AtomicUsize::new(level as usize),
+            initial: level,
+            floor,
+        };
+        control.set(level);
+        control
+    }
+
+    fn get(&self) -> log::LevelFilter {
+        LevelControl::from_usize(self.current.load(Ordering::Relaxed))
+    }
+
+    fn set(&self, level: log::LevelFilter) {
+        self.current.store(level as usize, Ordering::Relaxed);
+        log::set_max_level(::std::cmp::max(level, self.floor));
+    }
+
+    /// The level with the given discriminant, saturating at `Trace`
+    fn from_usize(value: usize) -> log::LevelFilter {
+        use log::LevelFilter::*;
+        const LEVELS: [log::LevelFilter; 6] = [Off, Error, Warn, Info, Debug, Trace];
+        LEVELS[::std::cmp::min(value, LEVELS.len() - 1)]
+    }
+}
+
+/// Number of lines the logger installed by `run` couldn't write
+///
+/// This is `Logger::dropped_count` for the global logger, suitable for
+/// reporting from `Application::metrics`. It's zero when no logger was
+/// installed by this crate.
+pub fn dropped_log_lines() -> u64 {
+    INSTALLED.get().map(|installed| installed.dropped.count()).unwrap_or(0)
+}
+
+/// Whether the output of the logger installed by `run` failed permanently
+///
+/// This is `Logger::output_broken` for the global logger, suitable for
+/// reporting degraded logging from `Application::health`.
+pub fn log_output_broken() -> bool {
+    INSTALLED.get().map(|installed| installed.dropped.output_broken()).unwrap_or(false)
+}
+
+/// Whether a write error means the output is gone for good
+///
+/// Retrying writes after one of these only wastes system calls.
+fn is_permanent(err: &io::Error) -> bool {
+    match err.kind() {
+        io::ErrorKind::BrokenPipe |
+        io::ErrorKind::ConnectionReset |
+        io::ErrorKind::NotConnected => true,
+        _ => err.raw_os_error() == Some(libc::EBADF),
+    }
+}
+
+/// Minimum time between warnings about dropped lines
+const DROPPED_WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Counts lines which couldn't be written, warning about them on stderr
+///
+/// The warning goes to stderr since the usual output is presumably what's
+/// failing. It's repeated at most once per `DROPPED_WARNING_INTERVAL`.
+///
+/// This also tracks whether the output failed permanently, in which case
+/// lines are dropped without trying to write them until the log file is
+/// reopened.
+pub(crate) struct Dropped {
+    count: AtomicU64,
+    warned: sync::Mutex<Option<Instant>>,
+    broken: AtomicBool,
+}
+
+impl Dropped {
+    fn new() -> Dropped {
+        Dropped {
+            count: AtomicU64::new(0),
+            warned: sync::Mutex::new(None),
+            broken: AtomicBool::new(false),
+        }
+    }
+
+    /// Count `lines` more lines which failed to be written with `err`
+    pub(crate) fn failed(&self, lines: u64, err: &io::Error, now: Instant) {
+        use std::io::Write;
+
+        if is_permanent(err) && !self.broken.swap(true, Ordering::SeqCst) {
+            let _ = writeln!(
+                io::stderr(),
+                "Log output failed ({}); discarding log lines until it's reopened",
+                err,
+            );
+        }
+        self.add(lines, now);
+    }
+
+    /// Whether writes should be skipped since the output failed permanently
+    pub(crate) fn output_broken(&self) -> bool {
+        self.broken.load(Ordering::SeqCst)
+    }
+
+    /// Try writing to the output again, after it's been reopened
+    fn repaired(&self) {
+        self.broken.store(false, Ordering::SeqCst);
+    }
+
+    /// Count `lines` more lines dropped at `now`
+    pub(crate) fn add(&self, lines: u64, now: Instant) {
+        use std::io::Write;
+
+        if lines == 0 {
+            return;
+        }
+
+        let total = self.count.fetch_add(lines, Ordering::Relaxed) + lines;
+        if let Ok(mut warned) = self.warned.lock() {
+            let due = warned
+                .map(|at| now.saturating_duration_since(at) >= DROPPED_WARNING_INTERVAL)
+                .unwrap_or(true);
+            if due {
+                *warned = Some(now);
+                let _ = writeln!(io::stderr(), "{} log lines dropped due to write errors", total);
+            }
+        }
+    }
+
+    fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use log;
+
+    use super::*;
+
+    struct Options {
+        info_as_notice: bool,
+    }
+
+    impl LogOptions for Options {
+        fn include_systemd_level(&self) -> bool { true }
+        fn info_as_notice(&self) -> bool { self.info_as_notice }
+        fn target_filter(&self) -> Vec<String> { vec!["app".into()] }
+        fn max_log_level(&self) -> log::LevelFilter { log::LevelFilter::Trace }
+    }
+
+    fn prefixes(info_as_notice: bool) -> Vec<&'static str> {
+        use log::Level::*;
+
+        let logger = Logger::new_unbuffered(io::sink(), &Options { info_as_notice });
+        [Error, Warn, Info, Debug, Trace]
+            .iter()
+            .map(|&level| {
+                logger.systemd_level(&log::Record::builder().level(level).target("app").build())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn info_maps_to_the_info_severity() {
+        assert_eq!(prefixes(false), ["<3> ", "<4> ", "<6> ", "<7> ", "<7> "]);
+    }
+
+    #[test]
+    fn info_as_notice_maps_info_to_the_notice_severity() {
+        assert_eq!(prefixes(true), ["<3> ", "<4> ", "<5> ", "<7> ", "<7> "]);
+    }
+}
@@ -0,0 +1,173 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! The global logger, which holds records until the real one is installed
+//!
+//! `log` only allows setting the global logger once, so the one installed is
+//! always `PROXY`, and the real logger is put behind it later.
+use std::sync::{Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use log;
+
+/// Most records held before the real logger is installed
+const CAPACITY: usize = 256;
+
+/// A record held until it can be replayed
+struct Early {
+    level: log::Level,
+    target: String,
+    message: String,
+    module_path: Option<String>,
+    file: Option<String>,
+    line: Option<u32>,
+    key_values: Vec<(String, String)>,
+}
+
+struct Proxy {
+    logger: OnceLock<Box<dyn log::Log>>,
+
+    /// `None` once the held records have been replayed
+    early: Mutex<Option<Vec<Early>>>,
+
+    /// Number of records which didn't fit in `early`
+    overflowed: AtomicUsize,
+}
+
+static PROXY: Proxy = Proxy {
+    logger: OnceLock::new(),
+    early: Mutex::new(Some(Vec::new())),
+    overflowed: AtomicUsize::new(0),
+};
+
+static PROXY_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Make `PROXY` the global logger, holding every record until `set` is called
+///
+/// Does nothing if it already is.
+pub fn install() -> Result<(), log::SetLoggerError> {
+    if !PROXY_INSTALLED.load(Ordering::SeqCst) {
+        install_proxy()?;
+
+        // Everything is held, and the real logger filters on replay.
+        log::set_max_level(log::LevelFilter::Trace);
+    }
+    Ok(())
+}
+
+fn install_proxy() -> Result<(), log::SetLoggerError> {
+    log::set_logger(&PROXY)?;
+    PROXY_INSTALLED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Send records to `logger` from now on, replaying those held so far
+///
+/// Records which didn't fit are reported with a warning on `target`.
+pub fn set(logger: Box<dyn log::Log>, target: &str) -> Result<(), log::SetLoggerError> {
+    if !PROXY_INSTALLED.load(Ordering::SeqCst) {
+        install_proxy()?;
+    }
+    if PROXY.logger.set(logger).is_err() {
+        // There's no other way to construct the error, and this is certain to
+        // fail since the proxy is installed.
+        return log::set_logger(&PROXY);
+    }
+
+    let logger = &**PROXY.logger.get().expect("logger was just set");
+    let early = PROXY.early.lock().ok().and_then(|mut early| early.take());
+    for record in early.unwrap_or_default() {
+        replay(logger, &record);
+    }
+
+    let overflowed = PROXY.overflowed.load(Ordering::SeqCst);
+    if overflowed > 0 {
+        warn!(target: target, "{} log records from startup were dropped", overflowed);
+    }
+
+    Ok(())
+}
+
+fn replay(logger: &dyn log::Log, record: &Early) {
+    let key_values: Vec<(&str, &str)> = record.key_values
+        .iter()
+        .map(|(key, value)| (&key[..], &value[..]))
+        .collect();
+
+    logger.log(&log::Record::builder()
+        .level(record.level)
+        .target(&record.target)
+        .args(format_args!("{}", record.message))
+        .module_path(record.module_path.as_ref().map(|m| &m[..]))
+        .file(record.file.as_ref().map(|f| &f[..]))
+        .line(record.line)
+        .key_values(&&key_values[..])
+        .build());
+}
+
+impl log::Log for Proxy {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        match self.logger.get() {
+            Some(logger) => logger.enabled(metadata),
+            None => true,
+        }
+    }
+
+    fn log(&self, record: &log::Record) {
+        if let Some(logger) = self.logger.get() {
+            return logger.log(record);
+        }
+
+        let mut held = match self.early.lock() {
+            Ok(held) => held,
+            Err(_) => return,
+        };
+
+        // The logger may have been set while waiting for the lock, in which
+        // case the held records have already been replayed.
+        let early = match *held {
+            Some(ref mut early) => early,
+            None => {
+                drop(held);
+                return self.log(record);
+            },
+        };
+
+        if early.len() >= CAPACITY {
+            self.overflowed.fetch_add(1, Ordering::SeqCst);
+            return;
+        }
+
+        let mut key_values = Vec::new();
+        let _ = super::for_each_pair(record, |key, value| {
+            key_values.push((key.to_string(), value.to_string()));
+            Ok(())
+        });
+
+        early.push(Early {
+            level: record.level(),
+            target: record.target().to_owned(),
+            message: record.args().to_string(),
+            module_path: record.module_path().map(|m| m.to_owned()),
+            file: record.file().map(|f| f.to_owned()),
+            line: record.line(),
+            key_values,
+        });
+    }
+
+    fn flush(&self) {
+        if let Some(logger) = self.logger.get() {
+            logger.flush();
+        }
+    }
+}
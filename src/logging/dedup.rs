@@ -0,0 +1,196 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Suppression of repeated log messages
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log;
+
+/// Number of distinct recent messages remembered
+const CAPACITY: usize = 128;
+
+struct Entry {
+    level: log::Level,
+    target: String,
+    message: String,
+    since: Instant,
+    repeated: u64,
+}
+
+/// Remembers recent messages to collapse identical ones within a window
+///
+/// Entries are kept most recently used first, and the least recently used is
+/// forgotten once `CAPACITY` is reached.
+pub struct Dedup {
+    window: Duration,
+    entries: Mutex<VecDeque<Entry>>,
+}
+
+impl Dedup {
+    pub fn new(window: Duration) -> Dedup {
+        Dedup {
+            window,
+            entries: Mutex::new(VecDeque::with_capacity(CAPACITY)),
+        }
+    }
+
+    /// Whether `record`, logged at `now`, should be emitted
+    ///
+    /// `report` is called with the level, target, and count of any message
+    /// whose repeats were suppressed and are now due to be reported, either
+    /// because its window closed or because it was forgotten.
+    pub fn check<F>(&self, record: &log::Record, now: Instant, mut report: F) -> bool
+        where F: FnMut(log::Level, &str, u64)
+    {
+        let message = record.args().to_string();
+        let mut entries = match self.entries.lock() {
+            Ok(entries) => entries,
+            Err(_) => return true,
+        };
+
+        self.expire_entries(&mut entries, now, &mut report);
+
+        let found = entries.iter().position(|e| {
+            e.level == record.level() && e.target == record.target() && e.message == message
+        });
+
+        match found.and_then(|i| entries.remove(i)) {
+            Some(mut entry) => {
+                let emit = if now.saturating_duration_since(entry.since) < self.window {
+                    entry.repeated += 1;
+                    false
+                } else {
+                    if entry.repeated > 0 {
+                        report(entry.level, &entry.target, entry.repeated);
+                    }
+                    entry.since = now;
+                    entry.repeated = 0;
+                    true
+                };
+
+                entries.push_front(entry);
+                emit
+            },
+            None => {
+                if entries.len() >= CAPACITY {
+                    if let Some(entry) = entries.pop_back() {
+                        if entry.repeated > 0 {
+                            report(entry.level, &entry.target, entry.repeated);
+                        }
+                    }
+                }
+
+                entries.push_front(Entry {
+                    level: record.level(),
+                    target: record.target().to_owned(),
+                    message,
+                    since: now,
+                    repeated: 0,
+                });
+                true
+            },
+        }
+    }
+
+    /// Report the suppressed repeats of messages whose window closed by `now`
+    ///
+    /// `check` does this too, so a flood which stops is reported once
+    /// anything else is logged, or here when the logger is flushed.
+    pub fn expire<F>(&self, now: Instant, mut report: F)
+        where F: FnMut(log::Level, &str, u64)
+    {
+        if let Ok(mut entries) = self.entries.lock() {
+            self.expire_entries(&mut entries, now, &mut report);
+        }
+    }
+
+    fn expire_entries<F>(&self, entries: &mut VecDeque<Entry>, now: Instant, report: &mut F)
+        where F: FnMut(log::Level, &str, u64)
+    {
+        for entry in entries.iter_mut() {
+            if entry.repeated > 0 && now.saturating_duration_since(entry.since) >= self.window {
+                report(entry.level, &entry.target, entry.repeated);
+                entry.repeated = 0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use log;
+
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(10);
+
+    /// Check `message` at `at` seconds, returning whether it's emitted and
+    /// the repeats reported by the check
+    fn check(dedup: &Dedup, message: &str, start: Instant, at: u64) -> (bool, Vec<String>) {
+        let mut reported = Vec::new();
+        let emit = dedup.check(
+            &log::Record::builder()
+                .level(log::Level::Warn)
+                .target("app")
+                .args(format_args!("{}", message))
+                .build(),
+            start + Duration::from_secs(at),
+            |_, _, repeated| reported.push(format!("repeated {}", repeated)),
+        );
+        (emit, reported)
+    }
+
+    #[test]
+    fn repeats_within_the_window_are_suppressed_and_counted() {
+        let dedup = Dedup::new(WINDOW);
+        let start = Instant::now();
+
+        assert_eq!(check(&dedup, "same", start, 0), (true, vec![]));
+        assert_eq!(check(&dedup, "same", start, 1), (false, vec![]));
+        assert_eq!(check(&dedup, "same", start, 2), (false, vec![]));
+
+        // The count is reported before the message starts a new window.
+        assert_eq!(check(&dedup, "same", start, 10), (true, vec!["repeated 2".into()]));
+        assert_eq!(check(&dedup, "same", start, 11), (false, vec![]));
+    }
+
+    #[test]
+    fn expired_repeats_are_reported_before_another_message() {
+        let dedup = Dedup::new(WINDOW);
+        let start = Instant::now();
+
+        check(&dedup, "first", start, 0);
+        check(&dedup, "first", start, 1);
+        assert_eq!(check(&dedup, "second", start, 5), (true, vec![]));
+        assert_eq!(check(&dedup, "third", start, 10), (true, vec!["repeated 1".into()]));
+    }
+
+    #[test]
+    fn expire_reports_each_count_once() {
+        let dedup = Dedup::new(WINDOW);
+        let start = Instant::now();
+        check(&dedup, "same", start, 0);
+        check(&dedup, "same", start, 1);
+
+        let mut reported = Vec::new();
+        dedup.expire(start + Duration::from_secs(9), |_, _, n| reported.push(n));
+        assert!(reported.is_empty());
+        dedup.expire(start + WINDOW, |_, _, n| reported.push(n));
+        dedup.expire(start + WINDOW * 2, |_, _, n| reported.push(n));
+        assert_eq!(reported, vec![1]);
+    }
+}
@@ -0,0 +1,70 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Logging with the native systemd journal protocol
+use std::io;
+use std::os::unix::net::UnixDatagram;
+
+use log;
+
+const SOCKET: &str = "/run/systemd/journal/socket";
+
+/// A connection to the journal's datagram socket
+pub struct Journal {
+    socket: UnixDatagram,
+    identifier: String,
+}
+
+impl Journal {
+    /// Connect to the journal, failing when it isn't available
+    pub fn connect() -> io::Result<Journal> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(SOCKET)?;
+
+        Ok(Journal { socket, identifier: super::identifier() })
+    }
+
+    /// Send a record at the given syslog priority
+    ///
+    /// Records too large for a single datagram are dropped.
+    pub fn send(&self, record: &log::Record, priority: usize) -> io::Result<()> {
+        let mut buf = Vec::new();
+        field(&mut buf, "PRIORITY", priority.to_string().as_bytes());
+        field(&mut buf, "MESSAGE", record.args().to_string().as_bytes());
+        field(&mut buf, "SYSLOG_IDENTIFIER", self.identifier.as_bytes());
+        if let Some(file) = record.file() {
+            field(&mut buf, "CODE_FILE", file.as_bytes());
+        }
+        if let Some(line) = record.line() {
+            field(&mut buf, "CODE_LINE", line.to_string().as_bytes());
+        }
+
+        self.socket.send(&buf).map(|_| ())
+    }
+}
+
+/// Append a field in the journal's export format
+///
+/// Values containing a newline are written with an explicit length instead of
+/// being newline terminated.
+fn field(buf: &mut Vec<u8>, name: &str, value: &[u8]) {
+    buf.extend_from_slice(name.as_bytes());
+    if value.contains(&b'\n') {
+        buf.push(b'\n');
+        buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        buf.push(b'=');
+    }
+    buf.extend_from_slice(value);
+    buf.push(b'\n');
+}
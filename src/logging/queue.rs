@@ -0,0 +1,140 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Writing log lines from a dedicated thread
+use std::io::{self, Write};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::Dropped;
+
+/// Longest a line may sit in a batch before being written
+const FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Batches at least this large are written immediately
+const BATCH_BYTES: usize = 64 * 1024;
+
+enum Message {
+    Line(Vec<u8>),
+    Flush(mpsc::Sender<()>),
+}
+
+/// A bounded queue of formatted lines consumed by a writer thread
+///
+/// Sending blocks when the queue is full, so lines are never dropped.
+pub struct Queue {
+    tx: mpsc::SyncSender<Message>,
+}
+
+impl Queue {
+    /// Spawn a thread writing queued lines to `output`
+    ///
+    /// Lines are combined into larger writes when `batch` is true, and
+    /// otherwise written one at a time. Lines which fail to be written are
+    /// counted in `dropped`.
+    pub fn spawn<W>(
+        output: W,
+        depth: usize,
+        batch: bool,
+        dropped: Arc<Dropped>,
+    ) -> io::Result<Queue>
+        where W: Write + Send + 'static
+    {
+        let (tx, rx) = mpsc::sync_channel(depth);
+        let batch_bytes = if batch { BATCH_BYTES } else { 0 };
+        thread::Builder::new()
+            .name("log-writer".into())
+            .spawn(move || writer_loop(output, &rx, batch_bytes, &dropped))?;
+
+        Ok(Queue { tx })
+    }
+
+    /// Queue a complete line, including its trailing newline
+    pub fn send(&self, line: Vec<u8>) {
+        let _ = self.tx.send(Message::Line(line));
+    }
+
+    /// Wait until everything queued so far has been written and flushed
+    pub fn flush(&self) {
+        let (ack, flushed) = mpsc::channel();
+        if self.tx.send(Message::Flush(ack)).is_ok() {
+            let _ = flushed.recv();
+        }
+    }
+}
+
+fn writer_loop<W: Write>(
+    mut output: W,
+    rx: &mpsc::Receiver<Message>,
+    batch_bytes: usize,
+    dropped: &Dropped,
+) {
+    let mut batch = Vec::new();
+    let mut deadline = Instant::now();
+
+    loop {
+        let message = if batch.is_empty() {
+            rx.recv().ok()
+        } else {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match rx.recv_timeout(timeout) {
+                Ok(message) => Some(message),
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    write_batch(&mut output, &mut batch, dropped);
+                    continue;
+                },
+                Err(mpsc::RecvTimeoutError::Disconnected) => None,
+            }
+        };
+
+        match message {
+            Some(Message::Line(line)) => {
+                if batch.is_empty() {
+                    deadline = Instant::now() + FLUSH_INTERVAL;
+                }
+
+                batch.extend_from_slice(&line);
+                if batch.len() >= batch_bytes {
+                    write_batch(&mut output, &mut batch, dropped);
+                }
+            },
+            Some(Message::Flush(ack)) => {
+                write_batch(&mut output, &mut batch, dropped);
+                if !dropped.output_broken() {
+                    let _ = output.flush();
+                }
+                let _ = ack.send(());
+            },
+            None => {
+                write_batch(&mut output, &mut batch, dropped);
+                if !dropped.output_broken() {
+                    let _ = output.flush();
+                }
+                return;
+            },
+        }
+    }
+}
+
+fn write_batch<W: Write>(output: &mut W, batch: &mut Vec<u8>, dropped: &Dropped) {
+    if !batch.is_empty() {
+        let lines = || batch.iter().filter(|&&b| b == b'\n').count() as u64;
+        if dropped.output_broken() {
+            dropped.add(lines(), Instant::now());
+        } else if let Err(err) = output.write_all(batch) {
+            dropped.failed(lines(), &err, Instant::now());
+        }
+        batch.clear();
+    }
+}
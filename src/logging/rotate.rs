@@ -0,0 +1,191 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Log files which rotate by size and by day
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clock::{Clock, SystemClock};
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// A log file which is rotated when it grows too large or a day passes
+///
+/// On rotation `app.log` is renamed to `app.log.1`, `app.log.1` to
+/// `app.log.2`, and so on, with the oldest beyond the number kept being
+/// deleted. Days are counted in UTC.
+///
+/// Rotation only happens between writes which end a line. Wrapping the writer
+/// in an `io::LineWriter`, as `Logger` does, keeps records from being split
+/// across files.
+pub struct RotatingFileWriter {
+    path: PathBuf,
+    file: fs::File,
+    max_bytes: Option<u64>,
+    keep: usize,
+    written: u64,
+    day: u64,
+    line_start: bool,
+    clock: Arc<dyn Clock>,
+}
+
+impl RotatingFileWriter {
+    /// Open `path` for appending, creating it if needed
+    ///
+    /// The file is rotated once it exceeds `max_bytes`, when given, and at
+    /// the first write on a later day than it was last modified. Up to `keep`
+    /// old files are retained.
+    pub fn open<P: Into<PathBuf>>(
+        path: P,
+        max_bytes: Option<u64>,
+        keep: usize,
+    ) -> io::Result<RotatingFileWriter> {
+        let path = path.into();
+        let file = open(&path)?;
+        let metadata = file.metadata()?;
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let day = metadata.modified().map(day_of).unwrap_or_else(|_| day_of(clock.now_system()));
+
+        Ok(RotatingFileWriter {
+            path,
+            file,
+            max_bytes,
+            keep,
+            written: metadata.len(),
+            day,
+            line_start: true,
+            clock,
+        })
+    }
+
+    /// Tell the day from `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> RotatingFileWriter {
+        self.clock = clock;
+        self
+    }
+
+    /// Switch to a fresh file at the path, as after an external `logrotate`
+    ///
+    /// Nothing happens if the path still refers to the open file, so repeated
+    /// requests are harmless. Pending output is flushed to the old file first,
+    /// and if the new one can't be opened, writing continues to the old one.
+    /// Returns whether a new file was opened.
+    pub fn reopen(&mut self) -> io::Result<bool> {
+        let current = self.file.metadata()?;
+        match fs::metadata(&self.path) {
+            Ok(ref at_path) if at_path.dev() == current.dev() && at_path.ino() == current.ino() => {
+                return Ok(false);
+            },
+            Ok(_) => (),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err),
+        }
+
+        self.file.flush()?;
+        let file = open(&self.path)?;
+        let metadata = file.metadata()?;
+
+        self.file = file;
+        self.written = metadata.len();
+        self.day = metadata.modified().map(day_of).unwrap_or_else(|_| self.today());
+
+        Ok(true)
+    }
+
+    fn should_rotate(&self, len: usize) -> bool {
+        if !self.line_start || self.written == 0 {
+            return false;
+        }
+
+        let too_big = match self.max_bytes {
+            Some(max) => self.written + len as u64 > max,
+            None => false,
+        };
+
+        too_big || self.today() != self.day
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        // Any of these may have been removed by someone else in the meantime,
+        // which is fine.
+        if self.keep == 0 {
+            ignore_missing(fs::remove_file(&self.path))?;
+        } else {
+            ignore_missing(fs::remove_file(numbered(&self.path, self.keep)))?;
+            for n in (1..self.keep).rev() {
+                let from = numbered(&self.path, n);
+                ignore_missing(fs::rename(&from, numbered(&self.path, n + 1)))?;
+            }
+            ignore_missing(fs::rename(&self.path, numbered(&self.path, 1)))?;
+        }
+
+        self.file = open(&self.path)?;
+        self.written = 0;
+        self.day = self.today();
+
+        Ok(())
+    }
+
+    fn today(&self) -> u64 {
+        day_of(self.clock.now_system())
+    }
+}
+
+impl Write for RotatingFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.should_rotate(buf.len()) && self.rotate().is_err() {
+            // Keep appending to the current file rather than losing lines,
+            // and wait for the next size or day boundary to try again.
+            self.written = 0;
+            self.day = self.today();
+        }
+
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        self.line_start = buf[..n].last().map_or(self.line_start, |&b| b == b'\n');
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn open(path: &Path) -> io::Result<fs::File> {
+    fs::OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// `path` with a `.n` suffix appended
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+fn ignore_missing(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+fn day_of(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs() / SECS_PER_DAY).unwrap_or(0)
+}
@@ -0,0 +1,155 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Logging to a syslog daemon with RFC 5424 messages
+use std::collections::VecDeque;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use humantime;
+use libc;
+use log;
+
+/// Messages are recorded with the "daemon" facility
+const FACILITY: usize = 3;
+
+/// Messages kept for retrying while the target is unreachable
+const PENDING_LIMIT: usize = 1024;
+
+/// Where syslog messages are sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyslogTarget {
+    /// A local datagram socket such as `/dev/log`
+    Unix(PathBuf),
+
+    /// A remote syslog server listening for UDP
+    Udp(SocketAddr),
+}
+
+enum Transport {
+    Unix(UnixDatagram, PathBuf),
+    Udp(UdpSocket, SocketAddr),
+}
+
+impl Transport {
+    fn send(&self, buf: &[u8]) -> io::Result<()> {
+        match *self {
+            Transport::Unix(ref socket, ref path) => socket.send_to(buf, path),
+            Transport::Udp(ref socket, addr) => socket.send_to(buf, addr),
+        }.map(|_| ())
+    }
+}
+
+struct State {
+    pending: VecDeque<Vec<u8>>,
+    dropped: u64,
+}
+
+/// A syslog client which never blocks or fails the caller
+///
+/// Messages which can't be sent are kept and retried before the next one.
+/// Once too many are waiting the oldest are dropped, and a count of the
+/// dropped messages is sent when the target becomes reachable again.
+pub struct Syslog {
+    transport: Transport,
+    hostname: String,
+    identifier: String,
+    state: Mutex<State>,
+}
+
+impl Syslog {
+    pub fn new(target: SyslogTarget) -> io::Result<Syslog> {
+        let transport = match target {
+            SyslogTarget::Unix(path) => Transport::Unix(UnixDatagram::unbound()?, path),
+            SyslogTarget::Udp(addr) => {
+                let local = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+                Transport::Udp(UdpSocket::bind(local)?, addr)
+            },
+        };
+
+        Ok(Syslog {
+            transport,
+            hostname: hostname(),
+            identifier: super::identifier(),
+            state: Mutex::new(State { pending: VecDeque::new(), dropped: 0 }),
+        })
+    }
+
+    /// Send a record at the given syslog severity, timestamped `now`
+    ///
+    /// Returns the number of waiting messages dropped to make room.
+    pub fn send(&self, record: &log::Record, severity: usize, now: SystemTime) -> u64 {
+        let message = self.format(severity, &record.args().to_string(), now);
+
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return 1,
+        };
+
+        state.pending.push_back(message);
+        while let Some(message) = state.pending.pop_front() {
+            if self.transport.send(&message).is_err() {
+                state.pending.push_front(message);
+                break;
+            }
+        }
+
+        if state.pending.is_empty() && state.dropped > 0 {
+            let notice = format!("{} log messages were dropped", state.dropped);
+            if self.transport.send(&self.format(4, &notice, now)).is_ok() {
+                state.dropped = 0;
+            }
+        }
+
+        let mut dropped = 0;
+        while state.pending.len() > PENDING_LIMIT {
+            state.pending.pop_front();
+            dropped += 1;
+        }
+        state.dropped += dropped;
+        dropped
+    }
+
+    fn format(&self, severity: usize, message: &str, now: SystemTime) -> Vec<u8> {
+        format!(
+            "<{}>1 {} {} {} {} - - {}",
+            FACILITY * 8 + severity,
+            humantime::format_rfc3339_millis(now),
+            nil_if_empty(&self.hostname),
+            nil_if_empty(&self.identifier),
+            process::id(),
+            message,
+        ).into_bytes()
+    }
+}
+
+/// RFC 5424 uses `-` for header fields without a value
+fn nil_if_empty(field: &str) -> &str {
+    if field.is_empty() { "-" } else { field }
+}
+
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    let ret = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if ret != 0 {
+        return String::new();
+    }
+
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
@@ -0,0 +1,99 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! PID file management
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use libc;
+
+use logging;
+
+/// A written PID file which is removed when dropped
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Write the current process id to `path`, which must not already exist
+    ///
+    /// A stale file, naming a process which isn't running, is replaced. When
+    /// the file names a running process, this fails with `AlreadyExists`.
+    pub fn create(path: PathBuf) -> io::Result<PidFile> {
+        let mut file = match create_new(&path) {
+            Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                if let Some(pid) = running_pid(&path) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("already running with pid {}", pid),
+                    ));
+                }
+
+                // Whoever else finds it stale also races to remove it, so
+                // only creating the new file need succeed.
+                if let Err(err) = fs::remove_file(&path) {
+                    if err.kind() != io::ErrorKind::NotFound {
+                        return Err(err);
+                    }
+                }
+                create_new(&path)?
+            },
+            result => result?,
+        };
+        writeln!(file, "{}", process::id())?;
+
+        Ok(PidFile { path })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            warn!(
+                target: logging::visible_target(),
+                "Failed to remove pid file {}: {}",
+                self.path.display(),
+                err,
+            );
+        }
+    }
+}
+
+fn create_new(path: &Path) -> io::Result<fs::File> {
+    fs::OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+/// Read a PID file and return the PID it names if that process is alive
+///
+/// Missing, unparsable, and stale PID files all return `None`.
+pub fn running_pid(path: &Path) -> Option<u32> {
+    let contents = fs::read_to_string(path).ok()?;
+    let pid = contents.trim().parse::<libc::pid_t>().ok()?;
+
+    if pid <= 0 || pid as u32 == process::id() {
+        return None;
+    }
+
+    // Signal 0 performs error checking only. EPERM means the process exists
+    // but belongs to someone else.
+    let alive = unsafe { libc::kill(pid, 0) } == 0 ||
+        io::Error::last_os_error().raw_os_error() == Some(libc::EPERM);
+
+    if alive {
+        Some(pid as u32)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,83 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! The items most services need, for a glob import
+//!
+//! Less common items, such as `ServiceInfo` or `Health`, are still imported
+//! from the crate root.
+//!
+//! ```rust,no_run
+//! # extern crate system_service;
+//! use system_service::prelude::*;
+//!
+//! #[derive(Clone)]
+//! struct MyOptions;
+//!
+//! impl LogOptions for MyOptions {
+//!     fn target_filter(&self) -> Vec<String> { vec!["my_app".into()] }
+//!     fn max_log_level(&self) -> LevelFilter { LevelFilter::Info }
+//! }
+//!
+//! impl Options for MyOptions {
+//!     fn load() -> Result<Self, OptionsError> { Ok(MyOptions) }
+//! }
+//!
+//! struct MyApplication;
+//!
+//! impl Application for MyApplication {
+//!     type Err = String;
+//!     type Config = NoConfig;
+//!     type Options = MyOptions;
+//!
+//!     fn new(_: MyOptions, _: NoConfig) -> Result<Self, String> {
+//!         Ok(MyApplication)
+//!     }
+//!
+//!     fn run_once(&mut self, context: &Context) -> Result<Stopping, String> {
+//!         Ok(context.poll_signals(self))
+//!     }
+//!
+//!     fn received_signal(&mut self, signal: Signal) -> Stopping {
+//!         match signal {
+//!             Signal::INT | Signal::TERM => Stopping::Yes,
+//!             _ => Stopping::No,
+//!         }
+//!     }
+//! }
+//!
+//! fn main() {
+//!     if let Err(err) = run::<MyApplication>() {
+//!         die!("Application encountered error: {}", err);
+//!     }
+//! }
+//! ```
+pub use application::{
+    Application,
+    Config,
+    ConfigError,
+    Context,
+    Loop,
+    NoConfig,
+    Options,
+    OptionsError,
+    SignalAction,
+    Stopping,
+};
+pub use logging::LogOptions;
+pub use run;
+
+pub use chan_signal::Signal;
+pub use log::LevelFilter;
+
+pub use die;
+pub use die_with;
@@ -0,0 +1,120 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Dropping root privileges to an unprivileged user
+use std::env;
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::ptr;
+
+use libc;
+
+/// A user to run as, resolved from the user database
+pub struct User {
+    pub name: String,
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+}
+
+impl User {
+    /// Look up a user by name
+    pub fn lookup(name: &str) -> io::Result<User> {
+        let c_name = CString::new(name)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "user name contains NUL"))?;
+
+        let mut buf = vec![0 as libc::c_char; 1024];
+        loop {
+            let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+            let mut result = ptr::null_mut();
+            let ret = unsafe {
+                libc::getpwnam_r(
+                    c_name.as_ptr(),
+                    &mut passwd,
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut result,
+                )
+            };
+
+            match ret {
+                0 if result.is_null() => {
+                    return Err(io::Error::new(io::ErrorKind::NotFound, "no such user"));
+                },
+                0 => {
+                    return Ok(User {
+                        name: name.to_owned(),
+                        uid: passwd.pw_uid,
+                        gid: passwd.pw_gid,
+                    });
+                },
+                libc::ERANGE => {
+                    let len = buf.len() * 2;
+                    buf.resize(len, 0);
+                },
+                err => return Err(io::Error::from_raw_os_error(err)),
+            }
+        }
+    }
+
+    /// Permanently switch the process to this user
+    ///
+    /// Supplementary groups and the gid are dropped before the uid since the
+    /// former can no longer be changed once the uid is unprivileged. The drop
+    /// is verified by confirming root can't be regained afterward.
+    pub fn switch_to(&self) -> io::Result<()> {
+        unsafe {
+            if libc::setgroups(1, &self.gid) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+
+            if libc::setgid(self.gid) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+
+            if libc::setuid(self.uid) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+
+            if libc::getuid() != self.uid || libc::geteuid() != self.uid ||
+                libc::getgid() != self.gid || libc::getegid() != self.gid
+            {
+                return Err(io::Error::other("ids did not change"));
+            }
+
+            if self.uid != 0 && (libc::setuid(0) != -1 || libc::setgid(0) != -1) {
+                return Err(io::Error::other("root privileges could be restored"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Change the root directory to `path` and move into it
+///
+/// This requires root, so it has to happen before `User::switch_to`.
+pub fn chroot(path: &Path) -> io::Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains NUL"))?;
+
+    if unsafe { libc::chroot(c_path.as_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // The old working directory is outside the new root and must not stay
+    // reachable.
+    env::set_current_dir("/")
+}
@@ -0,0 +1,71 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Naming the process as shown by `ps` and `top`
+use std::io;
+
+/// Longest name the kernel keeps, not counting the terminating nul
+#[cfg(target_os = "linux")]
+const MAX_LEN: usize = 15;
+
+/// Set the name of the calling thread, and so of the process when called from
+/// the main thread
+///
+/// Names longer than the kernel allows are truncated with a warning.
+#[cfg(target_os = "linux")]
+pub fn set(name: &str) -> io::Result<()> {
+    use std::ffi::CString;
+
+    use libc;
+
+    let name = truncate(name);
+    let name = CString::new(name).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidInput, "process name contains a nul byte")
+    })?;
+
+    let ptr = name.as_ptr() as libc::c_ulong;
+    if unsafe { libc::prctl(libc::PR_SET_NAME, ptr, 0, 0, 0) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Process names can't be set here, so this does nothing
+#[cfg(not(target_os = "linux"))]
+pub fn set(_name: &str) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn truncate(name: &str) -> &str {
+    use logging;
+
+    if name.len() <= MAX_LEN {
+        return name;
+    }
+
+    let mut end = MAX_LEN;
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    warn!(
+        target: logging::visible_target(),
+        "Process name {:?} is longer than {} bytes; using {:?}",
+        name,
+        MAX_LEN,
+        &name[..end],
+    );
+    &name[..end]
+}
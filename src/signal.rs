@@ -0,0 +1,111 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Helpers for working with `chan_signal::Signal`
+use std::io;
+use std::mem;
+use std::ptr;
+use std::thread;
+
+use chan;
+use chan_signal::Signal;
+use libc;
+
+/// Block `signals` and receive them from a thread named `signals`
+///
+/// `chan_signal::notify` does the same with an anonymous thread, which makes a
+/// hung process harder to read in a debugger. As with it, this must be called
+/// before any other thread is started so they all inherit the blocked mask,
+/// and a signal is dropped if 100 are already waiting.
+pub fn notify(signals: &[Signal]) -> io::Result<chan::Receiver<Signal>> {
+    let mut raw_signals = Vec::with_capacity(signals.len());
+    let mut set = unsafe {
+        let mut set = mem::zeroed::<libc::sigset_t>();
+        libc::sigemptyset(&mut set);
+        set
+    };
+    for &signal in signals {
+        let raw = raw(signal).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("unsupported signal {:?}", signal))
+        })?;
+        unsafe {
+            libc::sigaddset(&mut set, raw);
+        }
+        raw_signals.push((raw, signal));
+    }
+
+    let err = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut()) };
+    if err != 0 {
+        return Err(io::Error::from_raw_os_error(err));
+    }
+
+    let (tx, rx) = chan::sync(100);
+    thread::Builder::new()
+        .name("signals".into())
+        .spawn(move || loop {
+            let mut raw = 0;
+            if unsafe { libc::sigwait(&set, &mut raw) } != 0 {
+                continue;
+            }
+
+            if let Some(&(_, signal)) = raw_signals.iter().find(|&&(r, _)| r == raw) {
+                chan_select! {
+                    default => {},
+                    tx.send(signal) => {},
+                }
+            }
+        })?;
+
+    Ok(rx)
+}
+
+/// Map a signal to its raw number
+///
+/// `chan_signal` doesn't expose this mapping, but it's needed to talk to libc
+/// and other signal APIs directly.
+pub fn raw(signal: Signal) -> Option<libc::c_int> {
+    let raw = match signal {
+        Signal::HUP => libc::SIGHUP,
+        Signal::INT => libc::SIGINT,
+        Signal::QUIT => libc::SIGQUIT,
+        Signal::ILL => libc::SIGILL,
+        Signal::ABRT => libc::SIGABRT,
+        Signal::FPE => libc::SIGFPE,
+        Signal::KILL => libc::SIGKILL,
+        Signal::SEGV => libc::SIGSEGV,
+        Signal::PIPE => libc::SIGPIPE,
+        Signal::ALRM => libc::SIGALRM,
+        Signal::TERM => libc::SIGTERM,
+        Signal::USR1 => libc::SIGUSR1,
+        Signal::USR2 => libc::SIGUSR2,
+        Signal::CHLD => libc::SIGCHLD,
+        Signal::CONT => libc::SIGCONT,
+        Signal::STOP => libc::SIGSTOP,
+        Signal::TSTP => libc::SIGTSTP,
+        Signal::TTIN => libc::SIGTTIN,
+        Signal::TTOU => libc::SIGTTOU,
+        Signal::BUS => libc::SIGBUS,
+        Signal::PROF => libc::SIGPROF,
+        Signal::SYS => libc::SIGSYS,
+        Signal::TRAP => libc::SIGTRAP,
+        Signal::URG => libc::SIGURG,
+        Signal::VTALRM => libc::SIGVTALRM,
+        Signal::XCPU => libc::SIGXCPU,
+        Signal::XFSZ => libc::SIGXFSZ,
+        Signal::IO => libc::SIGIO,
+        Signal::WINCH => libc::SIGWINCH,
+        _ => return None,
+    };
+
+    Some(raw)
+}
@@ -0,0 +1,139 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Warnings about iterations of the main loop which run too long
+use std::io;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clock::Clock;
+
+struct State {
+    /// Iteration in progress and when it started, while armed
+    armed: Option<(u64, Instant)>,
+    stopped: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    wake: Condvar,
+    clock: Arc<dyn Clock>,
+}
+
+/// Logs a warning whenever an iteration runs past a timeout
+///
+/// A single thread does the waiting, so arming and disarming only take a
+/// lock. Warnings repeat for as long as the iteration keeps running, once per
+/// timeout. Dropping the monitor stops and joins its thread.
+pub struct StallMonitor {
+    shared: Arc<Shared>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl StallMonitor {
+    /// Start a monitor warning with `target` after `timeout` on `clock`
+    pub fn spawn(
+        timeout: Duration,
+        target: String,
+        clock: Arc<dyn Clock>,
+    ) -> io::Result<StallMonitor> {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State { armed: None, stopped: false }),
+            wake: Condvar::new(),
+            clock,
+        });
+
+        let thread = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("stall-monitor".into())
+                .spawn(move || monitor(&shared, timeout, &target))?
+        };
+
+        Ok(StallMonitor { shared, thread: Some(thread) })
+    }
+
+    /// Note that `iteration` is starting
+    pub fn arm(&self, iteration: u64) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.armed = Some((iteration, self.shared.clock.now_instant()));
+            self.shared.wake.notify_one();
+        }
+    }
+
+    /// Note that the iteration in progress has finished
+    pub fn disarm(&self) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.armed = None;
+        }
+    }
+}
+
+impl Drop for StallMonitor {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.stopped = true;
+            self.shared.wake.notify_one();
+        }
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn monitor(shared: &Shared, timeout: Duration, target: &str) {
+    let mut state = match shared.state.lock() {
+        Ok(state) => state,
+        Err(_) => return,
+    };
+
+    // The iteration last warned about, and when the next warning is due
+    let mut next_warning: Option<(u64, Instant)> = None;
+
+    while !state.stopped {
+        let (iteration, started) = match state.armed {
+            Some(armed) => armed,
+            None => {
+                state = match shared.wake.wait(state) {
+                    Ok(state) => state,
+                    Err(_) => return,
+                };
+                continue;
+            },
+        };
+
+        let due = match next_warning {
+            Some((warned, due)) if warned == iteration => due,
+            _ => started + timeout,
+        };
+
+        let now = shared.clock.now_instant();
+        if now >= due {
+            warn!(
+                target: target,
+                "run_once iteration {} has been running for {:?}",
+                iteration,
+                now.duration_since(started),
+            );
+            next_warning = Some((iteration, due + timeout));
+            continue;
+        }
+
+        state = match shared.wake.wait_timeout(state, due - now) {
+            Ok((state, _)) => state,
+            Err(_) => return,
+        };
+    }
+}
@@ -0,0 +1,305 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! A provided `Options` parsing the usual flags with clap
+use std::borrow::Cow;
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::{Arg, ArgAction, Command};
+use log;
+
+use application::{Options, OptionsError};
+use duration::parse_duration;
+use logging::{self, LogOptions};
+
+/// Defaults for the flags of `StandardOptions`
+///
+/// Implement this on a type of your own to change them, then use
+/// `StandardOptions<MyDefaults>`.
+pub trait StandardDefaults {
+    /// Default for `--config`; empty means no config file
+    const CONFIG_PATH: &'static str = "";
+
+    /// Default for `--log-level`
+    const LOG_LEVEL: log::LevelFilter = log::LevelFilter::Info;
+
+    /// Default for `--log-target`
+    ///
+    /// When empty, the program name is used with dashes replaced by
+    /// underscores, which matches the module path of a binary crate.
+    const LOG_TARGETS: &'static [&'static str] = &[];
+
+    /// Default for `--dependency-timeout`
+    const DEPENDENCY_TIMEOUT: Duration = Duration::from_secs(60);
+
+    /// Default for `--dependency-backoff`
+    const DEPENDENCY_BACKOFF: Duration = Duration::from_secs(1);
+}
+
+/// The defaults described on `StandardDefaults`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BuiltinDefaults;
+
+impl StandardDefaults for BuiltinDefaults {}
+
+/// Options for services which only need the basics
+///
+/// Use it in an application with `type Options = StandardOptions;`. These
+/// flags are accepted:
+///
+/// - `-c`, `--config <PATH>`: `Options::config_path`
+/// - `--log-level <LEVEL>`: `LogOptions::max_log_level`, one of `off`,
+///   `error`, `warn`, `info`, `debug`, or `trace`
+/// - `--log-target <TARGET>`: `LogOptions::target_filter`, and may be given
+///   more than once
+/// - `--systemd-level`: `LogOptions::include_systemd_level`
+/// - `--check-config`: `Options::check_only`
+/// - `-f`, `--foreground`: `Options::foreground`
+/// - `--dependency-timeout <DURATION>`: `Options::dependency_timeout`
+/// - `--dependency-backoff <DURATION>`: `Options::dependency_backoff`
+///
+/// Durations are parsed with `parse_duration`, such as `90s` or `2m`.
+/// Defaults come from `D`; see `StandardDefaults`.
+///
+/// ```rust
+/// # extern crate log;
+/// # extern crate system_service;
+/// use std::path::Path;
+/// use std::time::Duration;
+/// use system_service::{LogOptions, Options, StandardDefaults, StandardOptions};
+///
+/// struct MyDefaults;
+///
+/// impl StandardDefaults for MyDefaults {
+///     const CONFIG_PATH: &'static str = "/etc/my_app.toml";
+/// }
+///
+/// # fn main() {
+/// let args = vec![
+///     "my_app", "--log-level", "debug", "--log-target", "my_app",
+///     "--dependency-timeout", "2m",
+/// ];
+/// let options = StandardOptions::<MyDefaults>::parse_from(args).unwrap();
+///
+/// assert_eq!(options.config_path(), Path::new("/etc/my_app.toml"));
+/// assert_eq!(options.max_log_level(), log::LevelFilter::Debug);
+/// assert_eq!(options.target_filter(), vec!["my_app".to_owned()]);
+/// assert!(!options.include_systemd_level());
+/// assert!(!options.check_only());
+/// assert!(!options.foreground());
+/// assert_eq!(options.dependency_timeout(), Duration::from_secs(120));
+/// assert_eq!(options.dependency_backoff(), Duration::from_secs(1));
+/// # }
+/// ```
+pub struct StandardOptions<D = BuiltinDefaults> {
+    config_path: PathBuf,
+    log_level: log::LevelFilter,
+    log_targets: Vec<String>,
+    systemd_level: bool,
+    check_config: bool,
+    foreground: bool,
+    dependency_timeout: Duration,
+    dependency_backoff: Duration,
+    _defaults: PhantomData<fn() -> D>,
+}
+
+// Derived `Clone` would demand `D: Clone`, though no `D` is ever stored
+impl<D> Clone for StandardOptions<D> {
+    fn clone(&self) -> Self {
+        StandardOptions {
+            config_path: self.config_path.clone(),
+            log_level: self.log_level,
+            log_targets: self.log_targets.clone(),
+            systemd_level: self.systemd_level,
+            check_config: self.check_config,
+            foreground: self.foreground,
+            dependency_timeout: self.dependency_timeout,
+            dependency_backoff: self.dependency_backoff,
+            _defaults: PhantomData,
+        }
+    }
+}
+
+impl<D: StandardDefaults> StandardOptions<D> {
+    /// The clap command parsing these options
+    ///
+    /// Usage messages take the program name from the arguments.
+    pub fn command() -> Command {
+        Command::new("service")
+            .arg(Arg::new("config")
+                .short('c')
+                .long("config")
+                .value_name("PATH")
+                .help("Path to the config file")
+                .default_value(D::CONFIG_PATH))
+            .arg(Arg::new("log-level")
+                .long("log-level")
+                .value_name("LEVEL")
+                .help("Most verbose level to log: off, error, warn, info, debug, or trace")
+                .default_value(level_name(D::LOG_LEVEL)))
+            .arg(Arg::new("log-target")
+                .long("log-target")
+                .value_name("TARGET")
+                .help("Log messages whose target starts with this; may be repeated")
+                .action(ArgAction::Append))
+            .arg(Arg::new("systemd-level")
+                .long("systemd-level")
+                .help("Prefix lines with their systemd log level")
+                .action(ArgAction::SetTrue))
+            .arg(Arg::new("check-config")
+                .long("check-config")
+                .help("Validate the config file and exit")
+                .action(ArgAction::SetTrue))
+            .arg(Arg::new("foreground")
+                .short('f')
+                .long("foreground")
+                .help("Stay in the foreground and log to stderr")
+                .action(ArgAction::SetTrue))
+            .arg(Arg::new("dependency-timeout")
+                .long("dependency-timeout")
+                .value_name("DURATION")
+                .help("How long to wait for dependencies at startup, such as 60s")
+                .value_parser(parse_duration))
+            .arg(Arg::new("dependency-backoff")
+                .long("dependency-backoff")
+                .value_name("DURATION")
+                .help("Delay before first retrying unavailable dependencies, such as 1s")
+                .value_parser(parse_duration))
+    }
+
+    /// Parse options from `args`, whose first item is the program name
+    ///
+    /// `--help` prints usage and exits, as clap usually does.
+    pub fn parse_from<I, T>(args: I) -> Result<StandardOptions<D>, OptionsError>
+        where I: IntoIterator<Item = T>,
+              T: Into<OsString> + Clone
+    {
+        let matches = Self::command().try_get_matches_from(args).map_err(|err| {
+            match err.kind() {
+                clap::error::ErrorKind::DisplayHelp |
+                clap::error::ErrorKind::DisplayVersion => err.exit(),
+                _ => OptionsError::new(err.to_string()),
+            }
+        })?;
+
+        let config_path = matches.get_one::<String>("config")
+            .map(PathBuf::from)
+            .unwrap_or_default();
+
+        let log_level = match matches.get_one::<String>("log-level") {
+            Some(level) => level.parse().map_err(|_| {
+                OptionsError::new(format!("Invalid log level: {}", level))
+            })?,
+            None => D::LOG_LEVEL,
+        };
+
+        let mut log_targets: Vec<String> = matches.get_many::<String>("log-target")
+            .map(|targets| targets.cloned().collect())
+            .unwrap_or_default();
+        if log_targets.is_empty() {
+            log_targets = D::LOG_TARGETS.iter().map(|&t| t.to_owned()).collect();
+        }
+        if log_targets.is_empty() {
+            log_targets.push(logging::identifier().replace('-', "_"));
+        }
+
+        Ok(StandardOptions {
+            config_path,
+            log_level,
+            log_targets,
+            systemd_level: matches.get_flag("systemd-level"),
+            check_config: matches.get_flag("check-config"),
+            foreground: matches.get_flag("foreground"),
+            dependency_timeout: matches.get_one::<Duration>("dependency-timeout")
+                .cloned()
+                .unwrap_or(D::DEPENDENCY_TIMEOUT),
+            dependency_backoff: matches.get_one::<Duration>("dependency-backoff")
+                .cloned()
+                .unwrap_or(D::DEPENDENCY_BACKOFF),
+            _defaults: PhantomData,
+        })
+    }
+}
+
+impl<D> fmt::Debug for StandardOptions<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StandardOptions")
+            .field("config_path", &self.config_path)
+            .field("log_level", &self.log_level)
+            .field("log_targets", &self.log_targets)
+            .field("systemd_level", &self.systemd_level)
+            .field("check_config", &self.check_config)
+            .field("foreground", &self.foreground)
+            .field("dependency_timeout", &self.dependency_timeout)
+            .field("dependency_backoff", &self.dependency_backoff)
+            .finish()
+    }
+}
+
+impl<D> LogOptions for StandardOptions<D> {
+    fn include_systemd_level(&self) -> bool {
+        self.systemd_level
+    }
+
+    fn target_filter(&self) -> Vec<String> {
+        self.log_targets.clone()
+    }
+
+    fn max_log_level(&self) -> log::LevelFilter {
+        self.log_level
+    }
+}
+
+impl<D: StandardDefaults + 'static> Options for StandardOptions<D> {
+    fn load() -> Result<StandardOptions<D>, OptionsError> {
+        StandardOptions::parse_from(env::args_os())
+    }
+
+    fn config_path<'a>(&'a self) -> Cow<'a, Path> {
+        Cow::Borrowed(&self.config_path)
+    }
+
+    fn check_only(&self) -> bool {
+        self.check_config
+    }
+
+    fn foreground(&self) -> bool {
+        self.foreground
+    }
+
+    fn dependency_timeout(&self) -> Duration {
+        self.dependency_timeout
+    }
+
+    fn dependency_backoff(&self) -> Duration {
+        self.dependency_backoff
+    }
+}
+
+/// Lowercase name of a level as accepted by `--log-level`
+fn level_name(level: log::LevelFilter) -> &'static str {
+    use log::LevelFilter::*;
+    match level {
+        Off => "off",
+        Error => "error",
+        Warn => "warn",
+        Info => "info",
+        Debug => "debug",
+        Trace => "trace",
+    }
+}
@@ -0,0 +1,56 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Emitting metrics to a StatsD collector over UDP
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Duration;
+
+/// A fire-and-forget StatsD client
+///
+/// The socket is non-blocking and send errors are ignored, so a missing or
+/// slow collector never holds up the caller.
+pub struct Client {
+    socket: UdpSocket,
+    addr: SocketAddr,
+}
+
+impl Client {
+    pub fn new(addr: SocketAddr) -> io::Result<Client> {
+        let local = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = UdpSocket::bind(local)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(Client { socket, addr })
+    }
+
+    /// Record a duration in milliseconds
+    pub fn timing(&self, name: &str, duration: Duration) {
+        let millis = duration.as_secs_f64() * 1000.0;
+        self.send(&format!("{}:{:.3}|ms", name, millis));
+    }
+
+    /// Increment a counter by one
+    pub fn incr(&self, name: &str) {
+        self.send(&format!("{}:1|c", name));
+    }
+
+    /// Set a gauge to `value`
+    pub fn gauge(&self, name: &str, value: u64) {
+        self.send(&format!("{}:{}|g", name, value));
+    }
+
+    fn send(&self, metric: &str) {
+        let _ = self.socket.send_to(metric.as_bytes(), self.addr);
+    }
+}
@@ -0,0 +1,64 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Conventional exit statuses from BSD's `sysexits.h`
+//!
+//! These are understood by some supervisors and are handy with `die_with!`.
+
+/// Successful termination
+pub const OK: i32 = 0;
+
+/// The command was used incorrectly, e.g. with bad options
+pub const USAGE: i32 = 64;
+
+/// The input data was incorrect in some way
+pub const DATAERR: i32 = 65;
+
+/// An input file did not exist or was not readable
+pub const NOINPUT: i32 = 66;
+
+/// The specified user did not exist
+pub const NOUSER: i32 = 67;
+
+/// The specified host did not exist
+pub const NOHOST: i32 = 68;
+
+/// A service is unavailable
+pub const UNAVAILABLE: i32 = 69;
+
+/// An internal software error was detected
+pub const SOFTWARE: i32 = 70;
+
+/// An operating system error, such as failing to fork
+pub const OSERR: i32 = 71;
+
+/// A system file did not exist or had an error
+pub const OSFILE: i32 = 72;
+
+/// An output file could not be created
+pub const CANTCREAT: i32 = 73;
+
+/// An error occurred while doing I/O on some file
+pub const IOERR: i32 = 74;
+
+/// A temporary failure; the operation may succeed if retried
+pub const TEMPFAIL: i32 = 75;
+
+/// The remote system returned something invalid during a protocol exchange
+pub const PROTOCOL: i32 = 76;
+
+/// Insufficient permission to perform the operation
+pub const NOPERM: i32 = 77;
+
+/// Something was found in an unconfigured or misconfigured state
+pub const CONFIG: i32 = 78;
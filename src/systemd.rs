@@ -0,0 +1,121 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Integration with the systemd service manager
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::process;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Send a state string such as `READY=1` to the service manager
+///
+/// The message is written to the datagram socket named by `$NOTIFY_SOCKET`.
+/// When the variable isn't set, the service isn't running under a
+/// `Type=notify` unit and this is a no-op.
+pub fn notify(state: &str) -> io::Result<()> {
+    let path = match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+
+    let socket = UnixDatagram::unbound()?;
+    send_to(&socket, state.as_bytes(), &path)?;
+
+    Ok(())
+}
+
+/// Keep-alive pings for units configured with `WatchdogSec=`
+pub struct Watchdog {
+    interval: Duration,
+    last_ping: Mutex<Instant>,
+}
+
+impl Watchdog {
+    /// Read the watchdog interval from `$WATCHDOG_USEC`
+    ///
+    /// Returns `None` when the watchdog isn't enabled for this process. Pings
+    /// are due at half the configured interval to leave room for jitter,
+    /// counting from `now`.
+    pub fn from_env(now: Instant) -> Option<Watchdog> {
+        if let Ok(pid) = env::var("WATCHDOG_PID") {
+            if pid.parse::<u32>().ok() != Some(process::id()) {
+                return None;
+            }
+        }
+
+        let usec = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+        if usec == 0 {
+            return None;
+        }
+
+        Some(Watchdog {
+            interval: Duration::from_micros(usec) / 2,
+            last_ping: Mutex::new(now),
+        })
+    }
+
+    /// Send `WATCHDOG=1` unconditionally, recording `now` as the last ping
+    pub fn ping(&self, now: Instant) -> io::Result<()> {
+        if let Ok(mut last_ping) = self.last_ping.lock() {
+            *last_ping = now;
+        }
+
+        notify("WATCHDOG=1")
+    }
+
+    /// Send `WATCHDOG=1` if half the watchdog interval has elapsed by `now`
+    pub fn ping_if_due(&self, now: Instant) -> io::Result<()> {
+        let due = match self.last_ping.lock() {
+            Ok(last_ping) => now.saturating_duration_since(*last_ping) >= self.interval,
+            Err(_) => true,
+        };
+
+        if due {
+            self.ping(now)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Start counting towards the next ping from `now`
+    #[cfg(feature = "testing")]
+    pub fn reset(&self, now: Instant) {
+        if let Ok(mut last_ping) = self.last_ping.lock() {
+            *last_ping = now;
+        }
+    }
+}
+
+/// Send a datagram to a path or, when prefixed with `@`, an abstract socket
+#[cfg(target_os = "linux")]
+fn send_to(socket: &UnixDatagram, buf: &[u8], path: &OsStr) -> io::Result<usize> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::SocketAddr;
+
+    match path.as_bytes().split_first() {
+        Some((&b'@', name)) => {
+            let addr = SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(buf, &addr)
+        },
+        _ => socket.send_to(buf, path),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_to(socket: &UnixDatagram, buf: &[u8], path: &OsStr) -> io::Result<usize> {
+    socket.send_to(buf, path)
+}
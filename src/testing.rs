@@ -0,0 +1,280 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Helpers for testing an `Application` without running it as a service
+//!
+//! [`TestHarness`] drives an application the way [`run`] does, but without
+//! installing signal handlers, a global logger, or anything else which
+//! affects the whole process. Signals are delivered from an in-memory channel
+//! instead.
+//!
+//! ```rust
+//! # extern crate chan_signal;
+//! # extern crate log;
+//! # extern crate system_service;
+//! # use std::borrow::Cow;
+//! # use std::path::Path;
+//! use chan_signal::Signal;
+//! use system_service::*;
+//! use system_service::testing::TestHarness;
+//! # #[derive(Clone)]
+//! # struct MyOptions;
+//! # impl LogOptions for MyOptions {
+//! #     fn target_filter(&self) -> Vec<String> { vec!["my_app".into()] }
+//! #     fn max_log_level(&self) -> log::LevelFilter { log::LevelFilter::Info }
+//! # }
+//! # impl Options for MyOptions {
+//! #     fn load() -> Result<Self, OptionsError> { Ok(MyOptions) }
+//! #     fn config_path(&self) -> Cow<Path> { Cow::Borrowed(Path::new("my_app.toml")) }
+//! # }
+//! # struct MyConfig;
+//! # impl Config for MyConfig {
+//! #     fn load<O: Options>(_: &O) -> Result<Self, ConfigError> { Ok(MyConfig) }
+//! # }
+//!
+//! struct Counter {
+//!     count: u32,
+//! }
+//!
+//! impl Application for Counter {
+//!     type Err = String;
+//!     type Config = MyConfig;
+//!     type Options = MyOptions;
+//!
+//!     fn new(_: MyOptions, _: MyConfig) -> Result<Self, String> {
+//!         Ok(Counter { count: 0 })
+//!     }
+//!
+//!     fn run_once(&mut self, context: &Context) -> Result<Stopping, String> {
+//!         self.count += 1;
+//!         Ok(context.poll_signals(self))
+//!     }
+//! }
+//!
+//! # fn main() {
+//! let mut harness = TestHarness::<Counter>::new(MyOptions, MyConfig).unwrap();
+//! assert_eq!(harness.run(3).unwrap(), 3);
+//! assert_eq!(harness.app().count, 3);
+//! assert!(harness.context().options::<MyOptions>().is_some());
+//!
+//! harness.send_signal(Signal::TERM);
+//! assert_eq!(harness.run(3).unwrap(), 1);
+//! assert!(harness.context().stop_requested());
+//! # }
+//! ```
+//!
+//! Time can be controlled too by installing a [`MockClock`]:
+//!
+//! ```rust
+//! # extern crate system_service;
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//! use system_service::Context;
+//! use system_service::testing::MockClock;
+//!
+//! # fn main() {
+//! let (mut context, _signals) = Context::new_for_test();
+//! let clock = MockClock::new();
+//! context.set_clock(Arc::new(clock.clone()));
+//!
+//! clock.advance(Duration::from_secs(90));
+//! assert_eq!(context.uptime(), Duration::from_secs(90));
+//! # }
+//! ```
+//!
+//! [`TestHarness`]: struct.TestHarness.html
+//! [`MockClock`]: struct.MockClock.html
+//! [`run`]: ../fn.run.html
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use chan;
+use chan_signal::Signal;
+
+use application::{Application, Context, Loop, Stopping};
+use clock::Clock;
+
+/// Drives an `Application` with an in-memory signal channel
+pub struct TestHarness<T: Application> {
+    app: T,
+    context: Context,
+    signals: chan::Sender<Signal>,
+    completed: bool,
+}
+
+impl<T: Application> TestHarness<T> {
+    /// Create the application from `options` and `config`
+    ///
+    /// `Application::on_start` is called too, as `run` would.
+    pub fn new(options: T::Options, config: T::Config) -> Result<TestHarness<T>, T::Err> {
+        let kept = options.clone();
+        let retained = T::retain_options(&options);
+        let mut harness = TestHarness::with_app(T::new(options, config)?);
+        harness.context.keep_options(&kept);
+        if let Some(options) = retained {
+            harness.context.retain_options(options);
+        }
+        harness.app.on_start(&harness.context)?;
+        Ok(harness)
+    }
+
+    /// Drive an application which has already been created
+    ///
+    /// Unlike `new`, this doesn't call `Application::on_start`.
+    pub fn with_app(app: T) -> TestHarness<T> {
+        let (context, signals) = Context::new_for_test();
+        TestHarness { app, context, signals, completed: false }
+    }
+
+    /// Call `Application::run_step`, and so usually `run_once`, a single time
+    ///
+    /// Errors are passed to `Application::on_error` and returned only if it
+    /// says to stop, as in `run`. `Loop::Sleep` is treated as
+    /// `Stopping::No` without sleeping.
+    pub fn run_once(&mut self) -> Result<Stopping, T::Err> {
+        let stopping = match self.app.run_step(&self.context) {
+            Ok(Loop::Stop) => Stopping::Yes,
+            Ok(_) => Stopping::No,
+            Err(err) => {
+                self.context.error_occurred();
+                match self.app.on_error(&err) {
+                    Stopping::Yes => return Err(err),
+                    Stopping::No => Stopping::No,
+                }
+            },
+        };
+        self.context.iteration_completed();
+        if let Stopping::Yes = stopping {
+            self.completed = true;
+        }
+        Ok(stopping)
+    }
+
+    /// Call `Application::run_once` up to `iterations` times
+    ///
+    /// Like the main loop of `run`, this stops early once `run_once` returns
+    /// `Stopping::Yes`, `Application::max_iterations` is reached, or a stop is
+    /// requested and `Application::can_shutdown` allows it. Returns the number
+    /// of iterations completed.
+    pub fn run(&mut self, iterations: usize) -> Result<usize, T::Err> {
+        for completed in 1..=iterations {
+            if let Stopping::Yes = self.run_once()? {
+                return Ok(completed);
+            }
+
+            if let Some(max) = self.app.max_iterations() {
+                if self.context.iterations() >= max {
+                    self.completed = true;
+                    return Ok(completed);
+                }
+            }
+
+            if self.context.stop_requested() && self.context.stop_allowed(&self.app) {
+                return Ok(completed);
+            }
+        }
+
+        Ok(iterations)
+    }
+
+    /// Queue a signal to be seen by the next `Context::poll_signals`
+    ///
+    /// The signal is delivered whether or not it's in `Application::signals`.
+    pub fn send_signal(&self, signal: Signal) {
+        self.signals.send(signal);
+    }
+
+    /// Dispatch queued signals to the application outside of `run_once`
+    pub fn poll_signals(&mut self) -> Stopping {
+        self.context.poll_signals(&mut self.app)
+    }
+
+    /// The application being tested
+    pub fn app(&self) -> &T {
+        &self.app
+    }
+
+    /// Mutable access to the application being tested
+    pub fn app_mut(&mut self) -> &mut T {
+        &mut self.app
+    }
+
+    /// The context passed to `run_once`
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+
+    /// Read time from `clock` instead of the system clocks
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.context.set_clock(clock);
+    }
+
+    /// Call `Application::drain` and then `Application::shutdown_with`,
+    /// consuming the harness
+    ///
+    /// The reason is worked out as `run` would, and threads started with
+    /// `Context::spawn` are stopped and joined afterwards, phase by phase.
+    pub fn shutdown(mut self) -> Result<(), T::Err> {
+        self.app.drain()?;
+        let reason = self.context.shutdown_reason(self.completed);
+        let timeout = self.app.shutdown_timeout();
+        let result = self.app.shutdown_with(reason);
+        self.context.join_threads(timeout);
+        result
+    }
+}
+
+/// A clock which only moves when advanced
+///
+/// Clones share the same time, so one can be kept to advance a clock which
+/// has been handed to a `Context` or `Logger`.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    base: (Instant, SystemTime),
+    offset: Arc<Mutex<Duration>>,
+}
+
+impl MockClock {
+    /// A clock starting at the current time
+    pub fn new() -> MockClock {
+        MockClock {
+            base: (Instant::now(), SystemTime::now()),
+            offset: Arc::new(Mutex::new(Duration::from_secs(0))),
+        }
+    }
+
+    /// Move the clock forward by `duration`
+    pub fn advance(&self, duration: Duration) {
+        *self.offset.lock().unwrap() += duration;
+    }
+
+    fn offset(&self) -> Duration {
+        *self.offset.lock().unwrap()
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> MockClock {
+        MockClock::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now_instant(&self) -> Instant {
+        self.base.0 + self.offset()
+    }
+
+    fn now_system(&self) -> SystemTime {
+        self.base.1 + self.offset()
+    }
+}
@@ -0,0 +1,131 @@
+// Copyright 2018 OneSignal, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Watching the config file for changes
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use notify::{self, EventKind, RecursiveMode, Watcher};
+
+use logging;
+
+/// Watches a file and flags when it has settled after being changed
+///
+/// Dropping the watcher stops watching and joins its thread.
+pub struct ConfigWatcher {
+    changed: Arc<AtomicBool>,
+    watcher: Option<notify::RecommendedWatcher>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl ConfigWatcher {
+    /// Start watching `path`
+    ///
+    /// The parent directory is watched rather than the file itself since
+    /// editors commonly replace a file by writing a new one and renaming it
+    /// into place. A change is only reported once no further events have
+    /// arrived for `debounce`.
+    pub fn new(path: &Path, debounce: Duration) -> io::Result<ConfigWatcher> {
+        let path = path.canonicalize()?;
+        let dir = path.parent().map(Path::to_owned).unwrap_or_else(|| PathBuf::from("/"));
+
+        let (tx, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx).map_err(to_io_error)?;
+        watcher.watch(&dir, RecursiveMode::NonRecursive).map_err(to_io_error)?;
+
+        let changed = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let changed = changed.clone();
+            thread::Builder::new()
+                .name("config-watch".into())
+                .spawn(move || debounce_loop(&path, debounce, &events, &changed))?
+        };
+
+        Ok(ConfigWatcher {
+            changed,
+            watcher: Some(watcher),
+            thread: Some(thread),
+        })
+    }
+
+    /// Whether the file has changed since the last call
+    pub fn take_changed(&self) -> bool {
+        self.changed.swap(false, Ordering::SeqCst)
+    }
+
+    /// Forget any change observed so far
+    pub fn clear(&self) {
+        self.changed.store(false, Ordering::SeqCst);
+    }
+}
+
+impl Drop for ConfigWatcher {
+    fn drop(&mut self) {
+        // Dropping the watcher hangs up the event channel, which ends the
+        // debounce thread.
+        self.watcher.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+type Events = mpsc::Receiver<notify::Result<notify::Event>>;
+
+fn debounce_loop(path: &Path, debounce: Duration, events: &Events, changed: &AtomicBool) {
+    while let Ok(event) = events.recv() {
+        if !is_change_to(path, event) {
+            continue;
+        }
+
+        // Wait for the burst of events from a single save to finish.
+        loop {
+            match events.recv_timeout(debounce) {
+                Ok(_) => continue,
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            }
+        }
+
+        changed.store(true, Ordering::SeqCst);
+    }
+}
+
+fn is_change_to(path: &Path, event: notify::Result<notify::Event>) -> bool {
+    match event {
+        Ok(event) => {
+            let relevant = matches!(
+                event.kind,
+                EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+            );
+
+            relevant && event.paths.iter().any(|p| p == path)
+        },
+        Err(err) => {
+            warn!(target: logging::visible_target(), "Error watching config file: {}", err);
+            false
+        },
+    }
+}
+
+fn to_io_error(err: notify::Error) -> io::Error {
+    match err.kind {
+        notify::ErrorKind::Io(err) => err,
+        _ => io::Error::other(err),
+    }
+}